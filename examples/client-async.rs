@@ -1,4 +1,4 @@
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
 use image::{ImageBuffer, Rgb};
 use rvideo::ClientAsync;
@@ -13,7 +13,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut c = 0;
     while let Ok(frame) = client.read_next().await {
         let img: ImageBuffer<Rgb<u8>, Vec<u8>> =
-            ImageBuffer::from_vec(width, height, Arc::try_unwrap(frame.data).unwrap()).unwrap();
+            ImageBuffer::from_vec(width, height, Vec::from(frame.data)).unwrap();
         dbg!("frame");
         let metadata = if let Some(meta) = frame.metadata {
             rmp_serde::from_slice(&meta)?
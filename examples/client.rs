@@ -1,4 +1,4 @@
-use std::{sync::Arc, time::Duration};
+use std::time::Duration;
 
 use image::{ImageBuffer, Rgb};
 use serde_json::Value;
@@ -12,7 +12,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     for (c, frame) in client.enumerate() {
         let frame = frame?;
         let img: ImageBuffer<Rgb<u8>, Vec<u8>> =
-            ImageBuffer::from_vec(width, height, Arc::try_unwrap(frame.data).unwrap()).unwrap();
+            ImageBuffer::from_vec(width, height, Vec::from(frame.data)).unwrap();
         dbg!("frame");
         let metadata = if let Some(meta) = frame.metadata {
             rmp_serde::from_slice(&meta)?
@@ -0,0 +1,349 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rvideo::{Bytes, Client, Format, Frame, Server};
+use serde::Serialize;
+
+/// Frame metadata of synthetic streams: the sequence number and the send time (nanoseconds since
+/// the benchmark start)
+const METADATA_SIZE: usize = 16;
+
+#[derive(clap::Args)]
+pub struct BenchArgs {
+    #[clap(
+        short = 'f',
+        long,
+        default_value = "rgb8",
+        value_parser = parse_format,
+        help = "stream format (luma8, luma16, lumaa8, lumaa16, rgb8, rgb16, rgba8, rgba16)"
+    )]
+    format: Format,
+    #[clap(long, default_value = "1920")]
+    width: u16,
+    #[clap(long, default_value = "1080")]
+    height: u16,
+    #[clap(long, default_value = "30", help = "source FPS of each stream")]
+    fps: u16,
+    #[clap(short = 's', long, default_value = "1", help = "number of streams")]
+    streams: u16,
+    #[clap(
+        short = 'c',
+        long,
+        default_value = "1",
+        help = "number of clients (distributed between streams)"
+    )]
+    clients: usize,
+    #[clap(short = 'd', long, default_value = "10", help = "duration, seconds")]
+    duration: f64,
+    #[clap(short = 'l', long, default_value = "127.0.0.1:3101")]
+    listen: String,
+    #[clap(long, help = "print a JSON object")]
+    json: bool,
+}
+
+fn parse_format(s: &str) -> Result<Format, String> {
+    Ok(match s.to_lowercase().as_str() {
+        "luma8" => Format::Luma8,
+        "luma16" => Format::Luma16,
+        "lumaa8" => Format::LumaA8,
+        "lumaa16" => Format::LumaA16,
+        "rgb8" => Format::Rgb8,
+        "rgb16" => Format::Rgb16,
+        "rgba8" => Format::Rgba8,
+        "rgba16" => Format::Rgba16,
+        _ => return Err(format!("unsupported format: {}", s)),
+    })
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    format: String,
+    width: u16,
+    height: u16,
+    source_fps: u16,
+    streams: u16,
+    duration: f64,
+    clients: Vec<ClientReport>,
+    /// CPU time of the server (all threads, except sources and clients), seconds
+    server_cpu: Option<f64>,
+    /// Server CPU time per client, milliseconds per second
+    server_cpu_per_client: Option<f64>,
+}
+
+#[derive(Serialize, Default)]
+struct ClientReport {
+    id: usize,
+    stream_id: u16,
+    frames: usize,
+    fps: f64,
+    dropped: u64,
+    /// Frame latency percentiles, milliseconds: from sending the frame by the source to receiving
+    /// it by the client (the acknowledgment is not included)
+    frame_latency_p50: f64,
+    frame_latency_p90: f64,
+    frame_latency_p99: f64,
+    frame_latency_max: f64,
+    /// CPU time of the client thread, seconds
+    cpu: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// CPU time of the current process
+fn process_cpu_time() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        // SAFETY: the struct is initialized by getrusage
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+            return None;
+        }
+        let tv = |t: libc::timeval| {
+            Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+        };
+        Some(tv(usage.ru_utime) + tv(usage.ru_stime))
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// CPU time of the current thread
+fn thread_cpu_time() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        // SAFETY: the struct is initialized by clock_gettime
+        let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+        if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) } != 0 {
+            return None;
+        }
+        Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+fn percentile(sorted: &[u64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[i] as f64 / 1_000_000.0
+}
+
+/// Sends synthetic frames at the given rate, returns the thread CPU time
+fn run_source(
+    stream: rvideo::Stream,
+    data: Bytes,
+    fps: u16,
+    epoch: Instant,
+    running: &AtomicBool,
+) -> Option<Duration> {
+    let interval = Duration::from_secs(1) / u32::from(fps.max(1));
+    let mut next = Instant::now();
+    let mut seq: u64 = 0;
+    while running.load(Ordering::Relaxed) {
+        let mut metadata = Vec::with_capacity(METADATA_SIZE);
+        metadata.extend(seq.to_le_bytes());
+        metadata.extend((epoch.elapsed().as_nanos() as u64).to_le_bytes());
+        let _r = stream.send_frame(Frame {
+            metadata: Some(metadata.into()),
+            data: data.clone(),
+        });
+        seq += 1;
+        next += interval;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+    thread_cpu_time()
+}
+
+fn run_client(
+    addr: &str,
+    id: usize,
+    stream_id: u16,
+    epoch: Instant,
+    duration: Duration,
+) -> ClientReport {
+    let mut report = ClientReport {
+        id,
+        stream_id,
+        ..ClientReport::default()
+    };
+    let cpu_start = thread_cpu_time();
+    let result = (|| -> Result<(), rvideo::Error> {
+        let mut client = Client::connect(addr, Duration::from_secs(5))?;
+        client.select_stream(stream_id, u8::MAX)?;
+        let mut latencies = Vec::new();
+        let mut first_seq: Option<u64> = None;
+        let mut last_seq = 0;
+        let started = Instant::now();
+        for frame in client {
+            let frame = frame?;
+            let received = epoch.elapsed().as_nanos() as u64;
+            let Some(metadata) = frame.metadata.filter(|m| m.len() == METADATA_SIZE) else {
+                continue;
+            };
+            let seq = u64::from_le_bytes(metadata[..8].try_into().unwrap());
+            let sent = u64::from_le_bytes(metadata[8..].try_into().unwrap());
+            first_seq.get_or_insert(seq);
+            last_seq = seq;
+            latencies.push(received.saturating_sub(sent));
+            if started.elapsed() >= duration {
+                break;
+            }
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        report.frames = latencies.len();
+        report.fps = latencies.len() as f64 / elapsed;
+        report.dropped = first_seq.map_or(0, |first| {
+            (last_seq - first + 1).saturating_sub(latencies.len() as u64)
+        });
+        latencies.sort_unstable();
+        report.frame_latency_p50 = percentile(&latencies, 0.5);
+        report.frame_latency_p90 = percentile(&latencies, 0.9);
+        report.frame_latency_p99 = percentile(&latencies, 0.99);
+        report.frame_latency_max = percentile(&latencies, 1.0);
+        Ok(())
+    })();
+    if let Err(error) = result {
+        report.error = Some(error.to_string());
+    }
+    report.cpu = cpu_start
+        .zip(thread_cpu_time())
+        .map(|(start, end)| (end - start).as_secs_f64());
+    report
+}
+
+pub fn run(args: &BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if args.streams == 0 || args.clients == 0 || args.fps == 0 {
+        return Err("streams, clients and fps must be greater than zero".into());
+    }
+    let duration = Duration::from_secs_f64(args.duration.max(0.0));
+    let server = Server::new(Duration::from_secs(5));
+    // one more for the start check connection
+    server.set_max_clients(args.clients + 1);
+    let bytes_per_pixel = args
+        .format
+        .bytes_per_pixel()
+        .ok_or("raw stream format required")?;
+    let data = Bytes::from(vec![
+        0x80u8;
+        usize::from(args.width)
+            * usize::from(args.height)
+            * bytes_per_pixel
+    ]);
+    let mut streams = Vec::new();
+    for _ in 0..args.streams {
+        streams.push(server.add_stream(args.format, args.width, args.height)?);
+    }
+    {
+        let server = server.clone();
+        let listen = args.listen.clone();
+        thread::spawn(move || {
+            if let Err(error) = server.serve(listen.as_str()) {
+                eprintln!("Server error: {}", error);
+                std::process::exit(1);
+            }
+        });
+    }
+    // wait until the server is started
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Client::connect(args.listen.as_str(), Duration::from_secs(1)).is_err() {
+        if Instant::now() > deadline {
+            return Err("server not started".into());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    if !args.json {
+        println!(
+            "Running {} stream(s) {:?} {}x{} @ {} fps, {} client(s), {} s",
+            args.streams,
+            args.format,
+            args.width,
+            args.height,
+            args.fps,
+            args.clients,
+            args.duration
+        );
+    }
+    let epoch = Instant::now();
+    let running = AtomicBool::new(true);
+    let cpu_start = process_cpu_time();
+    let (source_cpu, clients): (Vec<Option<Duration>>, Vec<ClientReport>) =
+        thread::scope(|scope| {
+            let sources: Vec<_> = streams
+                .iter()
+                .map(|stream| {
+                    let (stream, data, running) = (stream.clone(), data.clone(), &running);
+                    scope.spawn(move || run_source(stream, data, args.fps, epoch, running))
+                })
+                .collect();
+            let clients: Vec<_> = (0..args.clients)
+                .map(|id| {
+                    let stream_id = streams[id % streams.len()].id();
+                    let addr = args.listen.as_str();
+                    scope.spawn(move || run_client(addr, id, stream_id, epoch, duration))
+                })
+                .collect();
+            let clients = clients.into_iter().map(|c| c.join().unwrap()).collect();
+            running.store(false, Ordering::Relaxed);
+            let sources = sources.into_iter().map(|s| s.join().unwrap()).collect();
+            (sources, clients)
+        });
+    let elapsed = epoch.elapsed().as_secs_f64();
+    let server_cpu = cpu_start.zip(process_cpu_time()).and_then(|(start, end)| {
+        let other: Option<Duration> = source_cpu
+            .into_iter()
+            .chain(clients.iter().map(|c| c.cpu.map(Duration::from_secs_f64)))
+            .sum();
+        other.map(|other| (end - start).saturating_sub(other).as_secs_f64())
+    });
+    let report = BenchReport {
+        format: format!("{:?}", args.format),
+        width: args.width,
+        height: args.height,
+        source_fps: args.fps,
+        streams: args.streams,
+        duration: elapsed,
+        server_cpu,
+        server_cpu_per_client: server_cpu.map(|cpu| cpu * 1000.0 / elapsed / clients.len() as f64),
+        clients,
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("Frame latency: from sending by the source to receiving by the client");
+    println!(
+        "{:>6} {:>6} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+        "client", "stream", "fps", "dropped", "p50 ms", "p90 ms", "p99 ms", "max ms", "cpu s"
+    );
+    for c in &report.clients {
+        if let Some(ref error) = c.error {
+            println!("{:>6} {:>6} error: {}", c.id, c.stream_id, error);
+            continue;
+        }
+        println!(
+            "{:>6} {:>6} {:>8.1} {:>8} {:>8.2} {:>8.2} {:>8.2} {:>8.2} {:>8}",
+            c.id,
+            c.stream_id,
+            c.fps,
+            c.dropped,
+            c.frame_latency_p50,
+            c.frame_latency_p90,
+            c.frame_latency_p99,
+            c.frame_latency_max,
+            c.cpu
+                .map_or_else(|| "-".to_owned(), |cpu| format!("{:.3}", cpu))
+        );
+    }
+    if let (Some(cpu), Some(per_client)) = (report.server_cpu, report.server_cpu_per_client) {
+        println!(
+            "Server CPU time: {:.3} s, {:.2} ms/s per client",
+            cpu, per_client
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+use rvideo::{
+    export::{export_client, FrameWriter, MetadataSidecar},
+    Format, Frame,
+};
+
+use crate::ConnectArgs;
+
+#[derive(clap::Args)]
+pub struct DumpArgs {
+    #[clap(flatten)]
+    connect: ConnectArgs,
+    #[clap(help = "stream ID")]
+    stream_id: u16,
+    #[clap(
+        short = 'o',
+        long,
+        help = "write frames into numbered files in the directory instead of stdout"
+    )]
+    output: Option<PathBuf>,
+    #[clap(short = 'n', long, help = "stop after the number of frames")]
+    count: Option<usize>,
+    #[clap(short = 'd', long, help = "stop after the duration, seconds")]
+    duration: Option<f64>,
+    #[clap(long, default_value = "255")]
+    max_fps: u8,
+    #[clap(
+        short = 'm',
+        long,
+        help = "write frame metadata (decoded from MessagePack) as JSON lines into the file"
+    )]
+    metadata: Option<PathBuf>,
+}
+
+enum Output {
+    Stdout(io::StdoutLock<'static>),
+    Files {
+        dir: PathBuf,
+        extension: &'static str,
+        frame_number: usize,
+    },
+}
+
+struct DumpWriter {
+    output: Output,
+    sidecar: Option<MetadataSidecar>,
+}
+
+impl FrameWriter for DumpWriter {
+    fn write_frame(&mut self, frame: &Frame, timestamp: Duration) -> Result<(), rvideo::Error> {
+        match self.output {
+            Output::Stdout(ref mut stdout) => stdout.write_all(&frame.data)?,
+            Output::Files {
+                ref dir,
+                extension,
+                ref mut frame_number,
+            } => {
+                fs::write(
+                    dir.join(format!("frame_{:06}.{}", frame_number, extension)),
+                    &frame.data,
+                )?;
+                *frame_number += 1;
+            }
+        }
+        if let Some(ref mut sidecar) = self.sidecar {
+            sidecar.write_frame(frame, timestamp)?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), rvideo::Error> {
+        if let Output::Stdout(ref mut stdout) = self.output {
+            stdout.flush()?;
+        }
+        if let Some(ref mut sidecar) = self.sidecar {
+            sidecar.finish()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run(args: &DumpArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = args.connect.connect(args.connect.timeout())?;
+    let info = client.select_stream(args.stream_id, args.max_fps)?;
+    // stdout may carry frames, so information goes to stderr
+    eprintln!("Stream {}", info);
+    let output = if let Some(ref dir) = args.output {
+        fs::create_dir_all(dir)?;
+        Output::Files {
+            dir: dir.clone(),
+            extension: if info.format == Format::MJpeg {
+                "jpg"
+            } else {
+                "raw"
+            },
+            frame_number: 0,
+        }
+    } else {
+        Output::Stdout(io::stdout().lock())
+    };
+    let mut writer = DumpWriter {
+        output,
+        sidecar: args
+            .metadata
+            .as_ref()
+            .map(MetadataSidecar::create)
+            .transpose()?,
+    };
+    let duration = args.duration.map(|d| Duration::from_secs_f64(d.max(0.0)));
+    match export_client(&mut client, &mut writer, args.count, duration) {
+        Ok(frames) => {
+            eprintln!("{} frame(s) written", frames);
+            Ok(())
+        }
+        // the reading process has been closed
+        Err(rvideo::Error::Io(error)) if error.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        Err(error) => Err(error.into()),
+    }
+}
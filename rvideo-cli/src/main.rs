@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use rvideo::Client;
+
+mod bench;
+mod dump;
+mod probe;
+
+#[derive(Parser)]
+#[clap(name = "rvideo", version)]
+struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// Connection options, common for all commands, which connect to servers
+#[derive(clap::Args)]
+struct ConnectArgs {
+    #[clap(help = "server, HOST[:PORT], the default port is 3001")]
+    server: String,
+    #[clap(long, default_value = "5")]
+    timeout: u16,
+    #[clap(
+        long,
+        help = "pre-shared secret, if the server requires authentication"
+    )]
+    secret: Option<String>,
+}
+
+impl ConnectArgs {
+    fn addr(&self) -> String {
+        if self.server.contains(':') {
+            self.server.clone()
+        } else {
+            format!("{}:3001", self.server)
+        }
+    }
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(u64::from(self.timeout))
+    }
+    fn connect(&self, timeout: Duration) -> Result<Client, rvideo::Error> {
+        let client = Client::connect(self.addr().as_str(), timeout)?;
+        Ok(if let Some(ref secret) = self.secret {
+            client.with_secret(secret.as_bytes())
+        } else {
+            client
+        })
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[clap(about = "list server streams and measure their frame rates")]
+    Probe(probe::ProbeArgs),
+    #[clap(
+        alias = "cat",
+        about = "write raw frames of a stream to stdout or into numbered files"
+    )]
+    Dump(dump::DumpArgs),
+    #[clap(about = "run a server with synthetic streams and clients, report the performance")]
+    Bench(bench::BenchArgs),
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    match args.command {
+        Command::Probe(args) => probe::run(&args),
+        Command::Dump(args) => dump::run(&args),
+        Command::Bench(args) => bench::run(&args),
+    }
+}
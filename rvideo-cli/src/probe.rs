@@ -0,0 +1,175 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use rvideo::StreamDescription;
+use serde::Serialize;
+
+use crate::ConnectArgs;
+
+#[derive(clap::Args)]
+pub struct ProbeArgs {
+    #[clap(flatten)]
+    connect: ConnectArgs,
+    #[clap(
+        short = 'w',
+        long,
+        default_value = "2",
+        help = "measurement window, seconds (0 - do not measure)"
+    )]
+    window: f64,
+    #[clap(
+        long,
+        default_value = "255",
+        help = "max FPS, requested for measurements"
+    )]
+    max_fps: u8,
+    #[clap(long, help = "print a JSON object")]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct ProbeReport {
+    server: String,
+    api_version: u8,
+    streams: Vec<StreamReport>,
+}
+
+#[derive(Serialize)]
+struct StreamReport {
+    id: u16,
+    name: Option<String>,
+    format: String,
+    width: u16,
+    height: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    measurement: Option<Measurement>,
+}
+
+#[derive(Serialize, Default)]
+struct Measurement {
+    frames: usize,
+    fps: f64,
+    // bytes per second, metadata included
+    bandwidth: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Receives frames of the stream during the window
+fn measure(args: &ConnectArgs, stream_id: u16, max_fps: u8, window: Duration) -> Measurement {
+    let mut measurement = Measurement::default();
+    // idle streams are reported with zero frames when the window is over
+    let mut client = match args.connect(window.min(args.timeout())) {
+        Ok(client) => client,
+        Err(error) => {
+            measurement.error = Some(error.to_string());
+            return measurement;
+        }
+    };
+    if let Err(error) = client.select_stream(stream_id, max_fps) {
+        measurement.error = Some(error.to_string());
+        return measurement;
+    }
+    let mut bytes = 0;
+    let mut started: Option<Instant> = None;
+    for frame in client {
+        let Ok(frame) = frame else {
+            break;
+        };
+        // the first frame starts the window, so the connection time is not counted
+        let Some(started) = started else {
+            started = Some(Instant::now());
+            continue;
+        };
+        if started.elapsed() > window {
+            break;
+        }
+        measurement.frames += 1;
+        bytes += frame.data.len() + frame.metadata.map_or(0, |m| m.len());
+    }
+    let secs = window.as_secs_f64();
+    measurement.fps = measurement.frames as f64 / secs;
+    measurement.bandwidth = bytes as f64 / secs;
+    measurement
+}
+
+pub fn run(args: &ProbeArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = args.connect.connect(args.connect.timeout())?;
+    let api_version = client.api_version();
+    let streams: Vec<StreamDescription> = client.streams()?;
+    drop(client);
+    let window = Duration::from_secs_f64(args.window.max(0.0));
+    let measurements: Vec<Option<Measurement>> = if window.is_zero() {
+        streams.iter().map(|_| None).collect()
+    } else {
+        thread::scope(|scope| {
+            let workers: Vec<_> = streams
+                .iter()
+                .map(|stream| {
+                    let stream_id = stream.info.id;
+                    scope.spawn(move || measure(&args.connect, stream_id, args.max_fps, window))
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| Some(worker.join().unwrap()))
+                .collect()
+        })
+    };
+    let report = ProbeReport {
+        server: args.connect.addr(),
+        api_version,
+        streams: streams
+            .into_iter()
+            .zip(measurements)
+            .map(|(stream, measurement)| StreamReport {
+                id: stream.info.id,
+                name: stream.name,
+                format: format!("{:?}", stream.info.format),
+                width: stream.info.width,
+                height: stream.info.height,
+                measurement,
+            })
+            .collect(),
+    };
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!("Server: {}", report.server);
+    println!("API version: {}", report.api_version);
+    println!("Streams: {}", report.streams.len());
+    for stream in report.streams {
+        print!(
+            "  #{}{}, WxH: {}x{}, Fmt: {}",
+            stream.id,
+            stream
+                .name
+                .map_or_else(String::new, |name| format!(" ({})", name)),
+            stream.width,
+            stream.height,
+            stream.format
+        );
+        match stream.measurement {
+            Some(Measurement {
+                error: Some(error), ..
+            }) => println!(", error: {}", error),
+            Some(m) => println!(", {:.1} fps, {}/s", m.fps, format_bytes(m.bandwidth)),
+            None => println!(),
+        }
+    }
+    Ok(())
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use clap::Parser;
+use rvideo::{Client, Server};
+
+#[derive(Parser)]
+struct Args {
+    #[clap(
+        required = true,
+        help = "upstream servers, HOST[:PORT], the default port is 3001"
+    )]
+    upstream: Vec<String>,
+    #[clap(short = 'l', long, default_value = "0.0.0.0:3001")]
+    listen: String,
+    #[clap(
+        long,
+        default_value = "255",
+        help = "max FPS, requested from upstreams"
+    )]
+    max_fps: u8,
+    #[clap(long, default_value = "5")]
+    timeout: u16,
+    #[clap(long, default_value = "128")]
+    max_clients: usize,
+    #[clap(
+        long,
+        help = "pre-shared secret, if upstream servers require authentication"
+    )]
+    secret: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let timeout = Duration::from_secs(u64::from(args.timeout));
+    let secret = args.secret.as_ref().map(String::as_bytes);
+    let server = Server::new(timeout);
+    server.set_max_clients(args.max_clients);
+    for mut source in args.upstream {
+        if !source.contains(':') {
+            source = format!("{}:3001", source);
+        }
+        let mut client = Client::connect(source.as_str(), timeout)?;
+        if let Some(secret) = secret {
+            client = client.with_secret(secret);
+        }
+        for upstream_stream in client.streams()? {
+            let info = upstream_stream.info;
+            let stream =
+                server.add_remote_stream(source.as_str(), info.id, args.max_fps, secret)?;
+            println!(
+                "Relaying {} stream {} as stream {}: {}",
+                source,
+                info.id,
+                stream.id(),
+                info
+            );
+        }
+    }
+    println!("Listening on {}", args.listen);
+    server.serve(args.listen.as_str())?;
+    Ok(())
+}
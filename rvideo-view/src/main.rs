@@ -1,7 +1,8 @@
 use std::{
+    collections::BTreeMap,
     sync::{
         atomic,
-        mpsc::{channel, Receiver, Sender},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
         Arc,
     },
     thread,
@@ -10,14 +11,16 @@ use std::{
 
 use clap::Parser;
 use eframe::egui;
-use egui::{Button, Color32, ColorImage, RichText};
+use egui::{Button, Color32, ColorImage, RichText, Sense, Slider};
 use image::{DynamicImage, ImageBuffer, ImageReader, Rgb, RgbImage};
 use imageproc::{drawing::draw_hollow_rect_mut, rect::Rect};
-use rvideo::{BoundingBox, StreamInfo};
+use rvideo::{BoundingBox, Click, ParamInfo, ParamKind, ParamValue, StreamInfo};
 use serde::Deserialize;
 use serde_json::Value;
 
 const FPS_REPORT_DELAY: Duration = Duration::from_secs(1);
+const PARAMS_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const PARAMS_RECONNECT_DELAY: Duration = Duration::from_secs(5);
 
 type MaybeFrame = Option<(RgbImage, Option<Value>, u32, u32)>;
 
@@ -33,6 +36,23 @@ struct Args {
     stream_id: u16,
     #[clap(short = 'r', long, default_value = "false")]
     auto_reconnect: bool,
+    #[clap(
+        long,
+        help = "pre-shared secret, if the server requires authentication"
+    )]
+    secret: Option<String>,
+}
+
+fn client_connect(
+    source: &str,
+    timeout: Duration,
+    secret: Option<&str>,
+) -> Result<rvideo::Client, rvideo::Error> {
+    if let Some(secret) = secret {
+        rvideo::Client::connect_with_secret(source, timeout, secret.as_bytes())
+    } else {
+        rvideo::Client::connect(source, timeout)
+    }
 }
 
 fn vec_u8_to_vec_u16(input: Vec<u8>) -> Vec<u16> {
@@ -43,15 +63,16 @@ fn vec_u8_to_vec_u16(input: Vec<u8>) -> Vec<u16> {
 }
 
 fn handle_connection(
-    client: rvideo::Client,
+    mut client: rvideo::Client,
     tx: Sender<MaybeFrame>,
+    events: &Receiver<Vec<u8>>,
     stream_info: StreamInfo,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let width = stream_info.width.into();
     let height = stream_info.height.into();
-    for frame in client {
+    while let Some(frame) = client.next() {
         let frame = frame?;
-        let img_data = Arc::try_unwrap(frame.data).unwrap();
+        let img_data = Vec::from(frame.data);
         let mut img: RgbImage = match stream_info.format {
             rvideo::Format::Luma8 => {
                 DynamicImage::ImageLuma8(ImageBuffer::from_raw(width, height, img_data).unwrap())
@@ -106,20 +127,51 @@ fn handle_connection(
             }
         }
         tx.send(Some((img, meta, width, height)))?;
+        while let Ok(event) = events.try_recv() {
+            client.send_event(&event)?;
+        }
     }
     Ok(())
 }
 
+fn handle_params(
+    source: &str,
+    timeout: Duration,
+    secret: Option<&str>,
+    tx: &Sender<Vec<ParamInfo>>,
+    rx: &Receiver<(String, ParamValue)>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = client_connect(source, timeout, secret)?;
+    loop {
+        let params = client.params()?;
+        // do not occupy a server client slot if there are no parameters
+        if params.is_empty() {
+            return Ok(());
+        }
+        tx.send(params)?;
+        match rx.recv_timeout(PARAMS_REFRESH_INTERVAL) {
+            Ok((name, value)) => {
+                if let Err(e) = client.set_param(&name, value) {
+                    eprintln!("Parameter {} set error: {:?}", name, e);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
 fn connect(
     source: &str,
     timeout: Duration,
+    secret: Option<&str>,
     stream_id: u16,
     max_fps: u8,
     auto_reconnect: bool,
 ) -> Result<(rvideo::Client, StreamInfo), Box<dyn std::error::Error>> {
     loop {
         println!("Connecting to {}...", source);
-        match rvideo::Client::connect(source, timeout) {
+        match client_connect(source, timeout, secret) {
             Ok(mut v) => match v.select_stream(stream_id, max_fps) {
                 Ok(stream_info) => return Ok((v, stream_info)),
                 Err(e) => {
@@ -149,9 +201,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let auto_reconnect = args.auto_reconnect;
     println!("Source: {}", source);
     let timeout = Duration::from_secs(u64::from(args.timeout));
+    let secret = args.secret;
     let (mut client, stream_info) = connect(
         &source,
         timeout,
+        secret.as_deref(),
         args.stream_id,
         args.max_fps,
         auto_reconnect,
@@ -165,12 +219,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         ..Default::default()
     };
     let (tx, rx) = channel();
+    let (events_tx, events_rx) = channel();
+    let (params_tx, params_rx) = channel();
+    let (param_set_tx, param_set_rx) = channel();
     let mut stream_info_c = stream_info.clone();
-    let source_c = source.clone();
     let online_beacon = Arc::new(atomic::AtomicBool::new(true));
     let online_beacon_c = online_beacon.clone();
+    let source_c = source.clone();
+    let secret_c = secret.clone();
+    thread::spawn(move || loop {
+        match handle_params(
+            &source_c,
+            timeout,
+            secret_c.as_deref(),
+            &params_tx,
+            &param_set_rx,
+        ) {
+            // the server has no parameters, polling is stopped
+            Ok(()) => break,
+            Err(e) if e.is::<RecvTimeoutError>() => break,
+            Err(e) => eprintln!("Parameters error: {:?}", e),
+        }
+        thread::sleep(PARAMS_RECONNECT_DELAY);
+    });
+    let source_c = source.clone();
     thread::spawn(move || {
-        while let Err(e) = handle_connection(client, tx.clone(), stream_info_c) {
+        while let Err(e) = handle_connection(client, tx.clone(), &events_rx, stream_info_c) {
             online_beacon_c.store(false, atomic::Ordering::Relaxed);
             tx.send(None).unwrap();
             eprintln!("Error: {:?}", e);
@@ -180,6 +254,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             (client, stream_info_c) = connect(
                 &source_c,
                 timeout,
+                secret.as_deref(),
                 args.stream_id,
                 args.max_fps,
                 auto_reconnect,
@@ -195,6 +270,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             egui_extras::install_image_loaders(&cc.egui_ctx);
             Ok(Box::new(MyApp {
                 rx,
+                events: events_tx,
+                params: Vec::new(),
+                params_rx,
+                param_set_tx,
                 stream_info,
                 source,
                 last_frame: None,
@@ -227,8 +306,35 @@ fn format_value(value: Value, join_object: &str) -> String {
     }
 }
 
+fn click_event(response: &egui::Response, width: u32, height: u32) -> Option<Vec<u8>> {
+    let button = if response.clicked() {
+        0
+    } else if response.secondary_clicked() {
+        1
+    } else if response.middle_clicked() {
+        2
+    } else {
+        return None;
+    };
+    let pos = response.interact_pointer_pos()? - response.rect.min;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let to_picture = |v: f32, size: f32, max: u32| {
+        ((v / size * max as f32) as u32).min(max.saturating_sub(1)) as u16
+    };
+    let click = Click {
+        x: to_picture(pos.x, response.rect.width(), width),
+        y: to_picture(pos.y, response.rect.height(), height),
+        button,
+    };
+    rmp_serde::to_vec_named(&BTreeMap::from([(".click", click)])).ok()
+}
+
 struct MyApp {
     rx: Receiver<MaybeFrame>,
+    events: Sender<Vec<u8>>,
+    params: Vec<ParamInfo>,
+    params_rx: Receiver<Vec<ParamInfo>>,
+    param_set_tx: Sender<(String, ParamValue)>,
     stream_info: StreamInfo,
     source: String,
     last_frame: Option<Instant>,
@@ -287,6 +393,33 @@ impl eframe::App for MyApp {
             .map(|(_, fps)| usize::from(*fps))
             .sum::<usize>()
             / self.fps.len();
+        while let Ok(params) = self.params_rx.try_recv() {
+            self.params = params;
+        }
+        if !self.params.is_empty() {
+            egui::SidePanel::right("params").show(ctx, |ui| {
+                ui.heading("Parameters");
+                for param in &mut self.params {
+                    let changed = match (param.kind, &mut param.value) {
+                        (ParamKind::Bool, ParamValue::Bool(v)) => {
+                            ui.checkbox(v, &param.name).changed()
+                        }
+                        (ParamKind::Int { min, max }, ParamValue::Int(v)) => ui
+                            .add(Slider::new(v, min..=max).text(&param.name))
+                            .changed(),
+                        (ParamKind::Float { min, max }, ParamValue::Float(v)) => ui
+                            .add(Slider::new(v, min..=max).text(&param.name))
+                            .changed(),
+                        _ => false,
+                    };
+                    if changed {
+                        self.param_set_tx
+                            .send((param.name.clone(), param.value))
+                            .ok();
+                    }
+                }
+            });
+        }
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::ScrollArea::both().show(ui, |ui| {
                 let texture = ui.ctx().load_texture("frame", egui_img, <_>::default());
@@ -312,7 +445,10 @@ impl eframe::App for MyApp {
                     "Stream: {} {}, Actual FPS: {}  {}",
                     self.source, self.stream_info, fps, anim_char
                 ));
-                ui.image(&texture);
+                let response = ui.add(egui::Image::new(&texture).sense(Sense::click()));
+                if let Some(event) = click_event(&response, width, height) {
+                    self.events.send(event).ok();
+                }
                 if let Some(meta) = maybe_meta {
                     ui.label(format_value(meta, "\n"));
                 }
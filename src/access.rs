@@ -0,0 +1,214 @@
+use std::{collections::BTreeSet, net::IpAddr, str::FromStr};
+
+use crate::Error;
+
+/// Client access policy. Maps client networks (CIDR) to streams, allowed for them. Clients,
+/// which addresses do not match any rule, are rejected. Control sessions (parameters) and
+/// publishing sessions must be allowed explicitly, the stream list of a control session contains
+/// the streams, allowed for the client, only.
+#[derive(Clone, Debug, Default)]
+pub struct AccessPolicy {
+    rules: Vec<AccessRule>,
+}
+
+#[derive(Clone, Debug)]
+struct AccessRule {
+    network: Network,
+    grant: Grant,
+}
+
+#[derive(Clone, Debug)]
+enum Grant {
+    Streams(BTreeSet<u16>),
+    AllStreams,
+    Control,
+    Publishing,
+}
+
+impl AccessPolicy {
+    /// Create a new policy, which rejects everyone
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Allow clients from the network to access the given streams
+    pub fn allow(
+        self,
+        network: &str,
+        streams: impl IntoIterator<Item = u16>,
+    ) -> Result<Self, Error> {
+        self.rule(network, Grant::Streams(streams.into_iter().collect()))
+    }
+    /// Allow clients from the network to access all streams
+    pub fn allow_all_streams(self, network: &str) -> Result<Self, Error> {
+        self.rule(network, Grant::AllStreams)
+    }
+    /// Allow clients from the network to open control sessions (parameters and the stream list)
+    pub fn allow_control(self, network: &str) -> Result<Self, Error> {
+        self.rule(network, Grant::Control)
+    }
+    /// Allow publishers from the network to push streams (if publishing is enabled on the server)
+    pub fn allow_publishing(self, network: &str) -> Result<Self, Error> {
+        self.rule(network, Grant::Publishing)
+    }
+    fn rule(mut self, network: &str, grant: Grant) -> Result<Self, Error> {
+        self.rules.push(AccessRule {
+            network: network.parse()?,
+            grant,
+        });
+        Ok(self)
+    }
+    /// Check if the address is allowed to connect
+    pub fn allows_address(&self, ip: IpAddr) -> bool {
+        self.rules.iter().any(|rule| rule.network.contains(ip))
+    }
+    /// Check if the address is allowed to access the stream
+    pub fn allows_stream(&self, ip: IpAddr, stream_id: u16) -> bool {
+        self.allows(ip, |grant| match grant {
+            Grant::Streams(streams) => streams.contains(&stream_id),
+            Grant::AllStreams => true,
+            Grant::Control | Grant::Publishing => false,
+        })
+    }
+    /// Check if the address is allowed to open control sessions
+    pub fn allows_control(&self, ip: IpAddr) -> bool {
+        self.allows(ip, |grant| matches!(grant, Grant::Control))
+    }
+    /// Check if the address is allowed to publish streams
+    pub fn allows_publishing(&self, ip: IpAddr) -> bool {
+        self.allows(ip, |grant| matches!(grant, Grant::Publishing))
+    }
+    fn allows(&self, ip: IpAddr, f: impl Fn(&Grant) -> bool) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.network.contains(ip) && f(&rule.grant))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let addr: IpAddr = addr.parse().map_err(|_| Error::InvalidAddress)?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max_prefix
+        } else {
+            prefix.parse().map_err(|_| Error::InvalidAddress)?
+        };
+        if prefix > max_prefix {
+            return Err(Error::InvalidAddress);
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{AccessPolicy, Network};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_network_parse() {
+        let net: Network = "192.168.1.0/24".parse().unwrap();
+        assert_eq!(net.prefix, 24);
+        let net: Network = "10.0.0.1".parse().unwrap();
+        assert_eq!(net.prefix, 32);
+        let net: Network = "fd00::/8".parse().unwrap();
+        assert_eq!(net.prefix, 8);
+        let net: Network = "::1".parse().unwrap();
+        assert_eq!(net.prefix, 128);
+        for invalid in [
+            "192.168.1.0/33",
+            "fd00::/129",
+            "192.168.1.0/x",
+            "host/24",
+            "",
+        ] {
+            assert!(invalid.parse::<Network>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_network_contains_v4() {
+        let net: Network = "192.168.1.0/24".parse().unwrap();
+        assert!(net.contains(ip("192.168.1.1")));
+        assert!(net.contains(ip("192.168.1.255")));
+        assert!(!net.contains(ip("192.168.2.1")));
+        // IPv4-mapped IPv6 addresses are matched as IPv4 ones
+        assert!(net.contains(ip("::ffff:192.168.1.10")));
+        assert!(!net.contains(ip("fd00::1")));
+        let all: Network = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(ip("8.8.8.8")));
+        let host: Network = "10.0.0.1".parse().unwrap();
+        assert!(host.contains(ip("10.0.0.1")));
+        assert!(!host.contains(ip("10.0.0.2")));
+    }
+
+    #[test]
+    fn test_network_contains_v6() {
+        let net: Network = "fd00:1::/32".parse().unwrap();
+        assert!(net.contains(ip("fd00:1::1")));
+        assert!(net.contains(ip("fd00:1:ffff::1")));
+        assert!(!net.contains(ip("fd00:2::1")));
+        assert!(!net.contains(ip("192.168.1.1")));
+        let all: Network = "::/0".parse().unwrap();
+        assert!(all.contains(ip("2001:db8::1")));
+        let host: Network = "::1".parse().unwrap();
+        assert!(host.contains(ip("::1")));
+        assert!(!host.contains(ip("::2")));
+    }
+
+    #[test]
+    fn test_policy() {
+        let policy = AccessPolicy::new()
+            .allow("10.0.0.0/8", [1, 2])
+            .unwrap()
+            .allow_all_streams("192.168.1.0/24")
+            .unwrap()
+            .allow_control("192.168.1.0/24")
+            .unwrap()
+            .allow_publishing("10.1.0.0/16")
+            .unwrap();
+        assert!(policy.allows_address(ip("10.2.3.4")));
+        assert!(!policy.allows_address(ip("172.16.0.1")));
+        assert!(policy.allows_stream(ip("10.2.3.4"), 1));
+        assert!(!policy.allows_stream(ip("10.2.3.4"), 3));
+        assert!(policy.allows_stream(ip("192.168.1.5"), 3));
+        assert!(!policy.allows_control(ip("10.2.3.4")));
+        assert!(policy.allows_control(ip("192.168.1.5")));
+        assert!(policy.allows_publishing(ip("10.1.2.3")));
+        assert!(!policy.allows_publishing(ip("192.168.1.5")));
+    }
+}
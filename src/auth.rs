@@ -0,0 +1,40 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub(crate) const AUTH_NONE: u8 = 0;
+pub(crate) const AUTH_HMAC_SHA256: u8 = 1;
+
+pub(crate) const NONCE_SIZE: usize = 32;
+pub(crate) const SIGNATURE_SIZE: usize = 32;
+
+pub(crate) type Nonce = [u8; NONCE_SIZE];
+pub(crate) type Signature = [u8; SIGNATURE_SIZE];
+
+/// Generates a random challenge nonce
+pub(crate) fn nonce() -> Result<Nonce, Error> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    getrandom::getrandom(&mut nonce).map_err(|e| Error::Io(e.into()))?;
+    Ok(nonce)
+}
+
+/// Signs a challenge nonce with a shared secret. If no secret is provided, an empty signature is
+/// returned (accepted by servers which do not require authentication for the selected stream).
+pub(crate) fn sign(secret: Option<&[u8]>, nonce: &Nonce) -> Signature {
+    let Some(secret) = secret else {
+        return [0u8; SIGNATURE_SIZE];
+    };
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(nonce);
+    mac.finalize().into_bytes().into()
+}
+
+/// Verifies a signature in constant time
+pub(crate) fn verify(secret: &[u8], nonce: &Nonce, signature: &Signature) -> bool {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(nonce);
+    mac.verify_slice(signature).is_ok()
+}
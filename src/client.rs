@@ -1,45 +1,134 @@
+#[cfg(all(feature = "shm", target_os = "linux"))]
+use std::os::unix::net::UnixStream;
 use std::{
     io::{Cursor, Read, Write},
-    net::{TcpStream, ToSocketAddrs},
+    net::{TcpStream, ToSocketAddrs, UdpSocket},
     time::Duration,
 };
 
 use binrw::BinRead;
 
-use crate::{Error, Frame, Greetings, StreamInfo, StreamSelect};
+use crate::{
+    auth::{self, Nonce, AUTH_HMAC_SHA256, AUTH_NONE, NONCE_SIZE},
+    check_select_status, check_server_error,
+    multicast::{self, Reassembler},
+    params::{
+        control_response_payload, pack, pack_control_request, ControlRequest, ParamList, ParamName,
+        StreamEntry, StreamList, StreamName,
+    },
+    read_frame_blocks, write_frame_blocks, Error, Frame, Greetings, ParamInfo, ParamValue,
+    StreamDescription, StreamInfo, StreamSelect, CLIENT_MSG_ACK, CLIENT_MSG_EVENT,
+    CONTROL_STREAM_ID, MAX_CONTROL_RESPONSE_LEN, MAX_EVENT_SIZE, PUBLISH_MSG_FRAME,
+    PUBLISH_MSG_KEEPALIVE, PUBLISH_STREAM_ID, STREAM_FLAG_MULTICAST,
+};
+#[cfg(all(feature = "shm", target_os = "linux"))]
+use crate::{
+    shm::{ShmFrame, ShmReader},
+    STREAM_FLAG_SHM,
+};
+
+trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
 
 /// Synchronous client
 pub struct Client {
-    stream: TcpStream,
+    stream: Box<dyn Transport>,
+    api_version: u8,
     streams_available: u16,
     ready: bool,
+    control: bool,
+    nonce: Option<Nonce>,
+    secret: Option<Vec<u8>>,
+    timeout: Duration,
+    multicast: Option<(UdpSocket, Reassembler)>,
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    unix: Option<UnixStream>,
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    shm: Option<ShmReader>,
 }
 
 impl Client {
     /// Connect to a server and create a client instance
     pub fn connect(addr: impl ToSocketAddrs, timeout: Duration) -> Result<Self, Error> {
-        let mut stream = TcpStream::connect_timeout(
-            &addr
-                .to_socket_addrs()?
-                .next()
-                .ok_or(Error::InvalidAddress)?,
+        Self::handshake(Box::new(tcp_connect(addr, timeout)?), timeout)
+    }
+    /// Connect to a server, which requires authentication, with a pre-shared secret
+    pub fn connect_with_secret(
+        addr: impl ToSocketAddrs,
+        timeout: Duration,
+        secret: &[u8],
+    ) -> Result<Self, Error> {
+        Ok(Self::connect(addr, timeout)?.with_secret(secret))
+    }
+    /// Connect to a TLS server. The server name is used to verify the server certificate.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        addr: impl ToSocketAddrs,
+        timeout: Duration,
+        server_name: &str,
+        config: std::sync::Arc<rustls::ClientConfig>,
+    ) -> Result<Self, Error> {
+        let server_name = rustls::pki_types::ServerName::try_from(server_name.to_owned())
+            .map_err(|_| Error::InvalidAddress)?;
+        let conn = rustls::ClientConnection::new(config, server_name)?;
+        Self::handshake(
+            Box::new(rustls::StreamOwned::new(conn, tcp_connect(addr, timeout)?)),
             timeout,
-        )?;
+        )
+    }
+    /// Connect to a server, listening on a Unix domain socket
+    #[cfg(unix)]
+    pub fn connect_unix(
+        path: impl AsRef<std::path::Path>,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
         stream.set_read_timeout(Some(timeout))?;
         stream.set_write_timeout(Some(timeout))?;
-        stream.set_nodelay(true)?;
+        #[cfg(all(feature = "shm", target_os = "linux"))]
+        let unix = stream.try_clone()?;
+        #[allow(unused_mut)]
+        let mut client = Self::handshake(Box::new(stream), timeout)?;
+        #[cfg(all(feature = "shm", target_os = "linux"))]
+        client.unix.replace(unix);
+        Ok(client)
+    }
+    /// Set a pre-shared secret to authenticate with (if required by the server). Must be set
+    /// before a stream is selected.
+    pub fn with_secret(mut self, secret: &[u8]) -> Self {
+        self.secret.replace(secret.to_vec());
+        self
+    }
+    fn handshake(mut stream: Box<dyn Transport>, timeout: Duration) -> Result<Self, Error> {
         let mut buf = [0u8; 4];
         stream.read_exact(&mut buf)?;
+        check_server_error(&buf)?;
         let greetings = Greetings::read(&mut Cursor::new(&buf))?;
         if greetings.api_version != crate::API_VERSION {
             return Err(Error::ApiVersion(greetings.api_version));
         }
+        let nonce = read_auth_challenge(&mut stream)?;
         Ok(Self {
             stream,
+            api_version: greetings.api_version,
             streams_available: greetings.streams_available,
             ready: false,
+            control: false,
+            nonce,
+            secret: None,
+            timeout,
+            multicast: None,
+            #[cfg(all(feature = "shm", target_os = "linux"))]
+            unix: None,
+            #[cfg(all(feature = "shm", target_os = "linux"))]
+            shm: None,
         })
     }
+    /// Get the API version, reported by the server
+    pub fn api_version(&self) -> u8 {
+        self.api_version
+    }
     /// Get the number of streams available
     pub fn streams_available(&self) -> u16 {
         self.streams_available
@@ -47,10 +136,54 @@ impl Client {
     /// Select a stream on the server. As soon as a stream is selected, the client is ready to
     /// receive frames (use the client as an iterator).
     pub fn select_stream(&mut self, stream_id: u16, max_fps: u8) -> Result<StreamInfo, Error> {
-        let stream_select = StreamSelect { stream_id, max_fps };
-        let mut writer = Cursor::new(Vec::new());
-        binrw::BinWrite::write(&stream_select, &mut writer)?;
-        self.stream.write_all(&writer.into_inner())?;
+        self.open_stream(stream_id, max_fps, 0)
+    }
+    /// Select a stream on the server and receive frames over shared memory (Linux only, the
+    /// client must be connected with [`Client::connect_unix`]). Use [`Client::next_shm_frame`] to
+    /// read frames without copying (the iterator can be used as well but copies frame data).
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    pub fn select_stream_shm(&mut self, stream_id: u16, max_fps: u8) -> Result<StreamInfo, Error> {
+        if self.unix.is_none() {
+            return Err(Error::UnsupportedTransport);
+        }
+        let stream_info = self.open_stream(stream_id, max_fps, STREAM_FLAG_SHM)?;
+        self.shm.replace(ShmReader::default());
+        Ok(stream_info)
+    }
+    /// Receive the next frame over shared memory (see [`Client::select_stream_shm`]). The frame
+    /// data is valid until the next frame is requested.
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    pub fn next_shm_frame(&mut self) -> Result<ShmFrame<'_>, Error> {
+        let (true, Some(mut unix), Some(shm)) = (self.ready, self.unix.as_ref(), self.shm.as_mut())
+        else {
+            return Err(Error::NotReady);
+        };
+        let frame = shm.read_frame(unix)?;
+        // the server writes the next frame into another ring slot
+        unix.write_all(&[CLIENT_MSG_ACK])?;
+        Ok(frame)
+    }
+    /// Select a stream on the server and receive frames from the stream UDP multicast group (the
+    /// server must have multicast enabled for the stream). Incomplete frames are dropped. The
+    /// connection to the server is kept open to send events.
+    pub fn select_stream_multicast(&mut self, stream_id: u16) -> Result<StreamInfo, Error> {
+        let stream_info = self.open_stream(stream_id, u8::MAX, STREAM_FLAG_MULTICAST)?;
+        let mut family = [0u8; 1];
+        self.stream.read_exact(&mut family)?;
+        let mut buf = vec![0u8; multicast::group_len(family[0])?];
+        self.stream.read_exact(&mut buf)?;
+        let group = multicast::unpack_group(family[0], &mut Cursor::new(buf))?;
+        self.multicast.replace((
+            multicast::receiver(group, self.timeout)?,
+            Reassembler::new(stream_id),
+        ));
+        Ok(stream_info)
+    }
+    fn open_stream(&mut self, stream_id: u16, max_fps: u8, flags: u8) -> Result<StreamInfo, Error> {
+        if self.control {
+            return Err(Error::NotReady);
+        }
+        self.select(stream_id, max_fps, flags)?;
         let mut buf = [0u8; 7];
         self.stream.read_exact(&mut buf)?;
         let stream_info = StreamInfo::read(&mut Cursor::new(&buf))?;
@@ -61,6 +194,119 @@ impl Client {
             Err(Error::InvalidStream)
         }
     }
+    /// List server parameters. Parameter methods switch the client into a control session, so
+    /// they can not be used after a stream is selected and vice versa.
+    pub fn params(&mut self) -> Result<Vec<ParamInfo>, Error> {
+        let response = self.control_request(&ControlRequest::ListParams)?;
+        let list = ParamList::read(&mut Cursor::new(control_response_payload(&response)?))?;
+        Ok(list.params.into_iter().map(Into::into).collect())
+    }
+    /// List server streams with their names. Uses a control session (see [`Self::params`]).
+    pub fn streams(&mut self) -> Result<Vec<StreamDescription>, Error> {
+        let response = self.control_request(&ControlRequest::ListStreams)?;
+        let list = StreamList::read(&mut Cursor::new(control_response_payload(&response)?))?;
+        Ok(list.streams.into_iter().map(Into::into).collect())
+    }
+    /// Get a server parameter value
+    pub fn param(&mut self, name: &str) -> Result<ParamValue, Error> {
+        let response = self.control_request(&ControlRequest::GetParam(ParamName::new(name)?))?;
+        Ok(ParamValue::read(&mut Cursor::new(
+            control_response_payload(&response)?,
+        ))?)
+    }
+    /// Set a server parameter value
+    pub fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), Error> {
+        let response =
+            self.control_request(&ControlRequest::SetParam(ParamName::new(name)?, value))?;
+        control_response_payload(&response)?;
+        Ok(())
+    }
+    /// Sends STREAM-SELECT (signed if the server requires authentication) and checks the result
+    fn select(&mut self, stream_id: u16, max_fps: u8, flags: u8) -> Result<(), Error> {
+        let mut buf = pack(&StreamSelect {
+            stream_id,
+            max_fps,
+            flags,
+        })?;
+        if let Some(ref nonce) = self.nonce {
+            buf.extend(auth::sign(self.secret.as_deref(), nonce));
+        }
+        self.stream.write_all(&buf)?;
+        let mut status = [0u8; 1];
+        self.stream.read_exact(&mut status)?;
+        check_select_status(status[0])
+    }
+    fn control_request(&mut self, request: &ControlRequest) -> Result<Vec<u8>, Error> {
+        if self.ready {
+            return Err(Error::NotReady);
+        }
+        if !self.control {
+            self.select(CONTROL_STREAM_ID, 0, 0)?;
+            self.control = true;
+        }
+        self.stream.write_all(&pack_control_request(request)?)?;
+        let mut len_buf = [0u8; 4];
+        self.stream.read_exact(&mut len_buf)?;
+        let len = usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::InvalidParam)?;
+        if len > MAX_CONTROL_RESPONSE_LEN {
+            return Err(Error::InvalidParam);
+        }
+        let mut response = vec![0u8; len];
+        self.stream.read_exact(&mut response)?;
+        Ok(response)
+    }
+    /// Opens a publishing session, returns the server stream id
+    pub(crate) fn publish(&mut self, info: &StreamInfo, name: &str) -> Result<u16, Error> {
+        if self.ready || self.control {
+            return Err(Error::NotReady);
+        }
+        self.select(PUBLISH_STREAM_ID, 0, 0)?;
+        let entry = pack(&StreamEntry {
+            info: info.clone(),
+            name: StreamName::new(Some(name)),
+        })?;
+        let mut buf = u32::try_from(entry.len()).unwrap().to_le_bytes().to_vec();
+        buf.extend(entry);
+        self.stream.write_all(&buf)?;
+        let mut result = [0u8; 3];
+        self.stream.read_exact(&mut result)?;
+        check_select_status(result[0])?;
+        Ok(u16::from_le_bytes([result[1], result[2]]))
+    }
+    /// Pushes a frame in a publishing session and waits for the acknowledgment
+    pub(crate) fn push_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.stream.write_all(&[PUBLISH_MSG_FRAME])?;
+        write_frame_blocks(&mut self.stream, frame)?;
+        let mut ack = [0u8; 1];
+        self.stream.read_exact(&mut ack)?;
+        if ack[0] == CLIENT_MSG_ACK {
+            Ok(())
+        } else {
+            Err(Error::NotReady)
+        }
+    }
+    /// Keeps an idle publishing session alive
+    pub(crate) fn push_keepalive(&mut self) -> Result<(), Error> {
+        self.stream.write_all(&[PUBLISH_MSG_KEEPALIVE])?;
+        Ok(())
+    }
+    /// Send an application-defined event to the server. The server processes events while the
+    /// stream is active, so the client must have a stream selected.
+    pub fn send_event(&mut self, data: &[u8]) -> Result<(), Error> {
+        if !self.ready {
+            return Err(Error::NotReady);
+        }
+        if data.len() > MAX_EVENT_SIZE {
+            return Err(Error::EventTooLarge);
+        }
+        let len = u32::try_from(data.len()).map_err(|_| Error::EventTooLarge)?;
+        let mut buf = Vec::with_capacity(data.len() + 5);
+        buf.push(CLIENT_MSG_EVENT);
+        buf.extend(len.to_le_bytes());
+        buf.extend(data);
+        self.stream.write_all(&buf)?;
+        Ok(())
+    }
 }
 
 impl Iterator for Client {
@@ -69,38 +315,59 @@ impl Iterator for Client {
         if !self.ready {
             return Some(Err(Error::NotReady));
         }
-        let mut len_buf = [0u8; 4];
-        if let Err(e) = self.stream.read_exact(&mut len_buf) {
-            return Some(Err(e.into()));
-        }
-        let Ok(len) = usize::try_from(u32::from_le_bytes(len_buf)) else {
-            return Some(Err(Error::FrameMetaDataTooLarge));
-        };
-        let metadata = if len > 0 {
-            let mut buf = vec![0u8; len];
-            if let Err(e) = self.stream.read_exact(&mut buf) {
-                return Some(Err(e.into()));
+        if let Some((ref socket, ref mut reassembler)) = self.multicast {
+            let mut buf = [0u8; multicast::DATAGRAM_SIZE];
+            loop {
+                let len = match socket.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) => return Some(Err(e.into())),
+                };
+                match reassembler.push(&buf[..len]) {
+                    Ok(Some(frame)) => return Some(Ok(frame)),
+                    Ok(None) => {}
+                    Err(e) => return Some(Err(e)),
+                }
             }
-            Some(buf)
-        } else {
-            None
-        };
-        if let Err(e) = self.stream.read_exact(&mut len_buf) {
-            return Some(Err(e.into()));
         }
-        let Ok(len) = usize::try_from(u32::from_le_bytes(len_buf)) else {
-            return Some(Err(Error::FrameDataTooLarge));
+        #[cfg(all(feature = "shm", target_os = "linux"))]
+        if self.shm.is_some() {
+            return Some(self.next_shm_frame().map(|frame| frame.to_frame()));
+        }
+        let frame = match read_frame_blocks(&mut self.stream) {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
         };
-        let mut data = vec![0u8; len];
-        if let Err(e) = self.stream.read_exact(&mut data) {
+        if let Err(e) = self.stream.write_all(&[CLIENT_MSG_ACK]) {
             return Some(Err(e.into()));
         }
-        if let Err(e) = self.stream.write_all(&[0u8; 1]) {
-            return Some(Err(e.into()));
+        Some(Ok(frame))
+    }
+}
+
+fn tcp_connect(addr: impl ToSocketAddrs, timeout: Duration) -> Result<TcpStream, Error> {
+    let stream = TcpStream::connect_timeout(
+        &addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or(Error::InvalidAddress)?,
+        timeout,
+    )?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+fn read_auth_challenge(stream: &mut impl Read) -> Result<Option<Nonce>, Error> {
+    let mut auth_mode = [0u8; 1];
+    stream.read_exact(&mut auth_mode)?;
+    match auth_mode[0] {
+        AUTH_NONE => Ok(None),
+        AUTH_HMAC_SHA256 => {
+            let mut nonce = [0u8; NONCE_SIZE];
+            stream.read_exact(&mut nonce)?;
+            Ok(Some(nonce))
         }
-        Some(Ok(Frame {
-            metadata: metadata.map(Into::into),
-            data: data.into(),
-        }))
+        v => Err(Error::UnsupportedAuth(v)),
     }
 }
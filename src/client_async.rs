@@ -2,38 +2,121 @@ use std::{io::Cursor, time::Duration};
 
 use binrw::BinRead;
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::{TcpStream, ToSocketAddrs},
 };
 
-use crate::{Error, Frame, Greetings, StreamInfo, StreamSelect};
+use crate::{
+    auth::{self, Nonce, AUTH_HMAC_SHA256, AUTH_NONE, NONCE_SIZE},
+    check_select_status, check_server_error,
+    multicast::{self, Reassembler},
+    params::{
+        control_response_payload, pack, pack_control_request, ControlRequest, ParamList, ParamName,
+        StreamList,
+    },
+    Error, Frame, Greetings, ParamInfo, ParamValue, StreamDescription, StreamInfo, StreamSelect,
+    CLIENT_MSG_ACK, CLIENT_MSG_EVENT, CONTROL_STREAM_ID, MAX_CONTROL_RESPONSE_LEN, MAX_EVENT_SIZE,
+    STREAM_FLAG_MULTICAST,
+};
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
 
 /// Asynchronous client
 pub struct ClientAsync {
-    stream: TcpStream,
+    stream: Box<dyn Transport>,
+    api_version: u8,
     streams_available: u16,
     ready: bool,
+    control: bool,
+    nonce: Option<Nonce>,
+    secret: Option<Vec<u8>>,
     timeout: Duration,
+    multicast: Option<(tokio::net::UdpSocket, Reassembler)>,
 }
 
 impl ClientAsync {
     /// Connect to a server and create a client instance
     pub async fn connect(addr: impl ToSocketAddrs, timeout: Duration) -> Result<Self, Error> {
-        let mut stream = tokio::time::timeout(timeout, TcpStream::connect(addr)).await??;
-        stream.set_nodelay(true)?;
+        Self::handshake(Box::new(tcp_connect(addr, timeout).await?), timeout).await
+    }
+    /// Connect to a server, which requires authentication, with a pre-shared secret
+    pub async fn connect_with_secret(
+        addr: impl ToSocketAddrs,
+        timeout: Duration,
+        secret: &[u8],
+    ) -> Result<Self, Error> {
+        Ok(Self::connect(addr, timeout).await?.with_secret(secret))
+    }
+    /// Connect to a TLS server. The server name is used to verify the server certificate.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        addr: impl ToSocketAddrs,
+        timeout: Duration,
+        server_name: &str,
+        config: std::sync::Arc<rustls::ClientConfig>,
+    ) -> Result<Self, Error> {
+        let server_name = rustls::pki_types::ServerName::try_from(server_name.to_owned())
+            .map_err(|_| Error::InvalidAddress)?;
+        let stream = tcp_connect(addr, timeout).await?;
+        let stream = tokio::time::timeout(
+            timeout,
+            tokio_rustls::TlsConnector::from(config).connect(server_name, stream),
+        )
+        .await??;
+        Self::handshake(Box::new(stream), timeout).await
+    }
+    /// Connect to a server, listening on a Unix domain socket
+    #[cfg(unix)]
+    pub async fn connect_unix(
+        path: impl AsRef<std::path::Path>,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let stream = tokio::time::timeout(timeout, tokio::net::UnixStream::connect(path)).await??;
+        Self::handshake(Box::new(stream), timeout).await
+    }
+    /// Set a pre-shared secret to authenticate with (if required by the server). Must be set
+    /// before a stream is selected.
+    pub fn with_secret(mut self, secret: &[u8]) -> Self {
+        self.secret.replace(secret.to_vec());
+        self
+    }
+    async fn handshake(mut stream: Box<dyn Transport>, timeout: Duration) -> Result<Self, Error> {
         let mut buf = [0u8; 4];
         tokio::time::timeout(timeout, stream.read_exact(&mut buf)).await??;
+        check_server_error(&buf)?;
         let greetings = Greetings::read(&mut Cursor::new(&buf))?;
         if greetings.api_version != crate::API_VERSION {
             return Err(Error::ApiVersion(greetings.api_version));
         }
+        let mut auth_mode = [0u8; 1];
+        tokio::time::timeout(timeout, stream.read_exact(&mut auth_mode)).await??;
+        let nonce = match auth_mode[0] {
+            AUTH_NONE => None,
+            AUTH_HMAC_SHA256 => {
+                let mut nonce = [0u8; NONCE_SIZE];
+                tokio::time::timeout(timeout, stream.read_exact(&mut nonce)).await??;
+                Some(nonce)
+            }
+            v => return Err(Error::UnsupportedAuth(v)),
+        };
         Ok(Self {
             stream,
+            api_version: greetings.api_version,
             streams_available: greetings.streams_available,
             ready: false,
+            control: false,
+            nonce,
+            secret: None,
             timeout,
+            multicast: None,
         })
     }
+    /// Get the API version, reported by the server
+    pub fn api_version(&self) -> u8 {
+        self.api_version
+    }
     /// Get the number of streams available
     pub fn streams_available(&self) -> u16 {
         self.streams_available
@@ -45,10 +128,38 @@ impl ClientAsync {
         stream_id: u16,
         max_fps: u8,
     ) -> Result<StreamInfo, Error> {
-        let stream_select = StreamSelect { stream_id, max_fps };
-        let mut writer = Cursor::new(Vec::new());
-        binrw::BinWrite::write(&stream_select, &mut writer)?;
-        tokio::time::timeout(self.timeout, self.stream.write_all(&writer.into_inner())).await??;
+        self.open_stream(stream_id, max_fps, 0).await
+    }
+    /// Select a stream on the server and receive frames from the stream UDP multicast group (the
+    /// server must have multicast enabled for the stream). Incomplete frames are dropped. The
+    /// connection to the server is kept open to send events.
+    pub async fn select_stream_multicast(&mut self, stream_id: u16) -> Result<StreamInfo, Error> {
+        let stream_info = self
+            .open_stream(stream_id, u8::MAX, STREAM_FLAG_MULTICAST)
+            .await?;
+        let mut family = [0u8; 1];
+        tokio::time::timeout(self.timeout, self.stream.read_exact(&mut family)).await??;
+        let mut buf = vec![0u8; multicast::group_len(family[0])?];
+        tokio::time::timeout(self.timeout, self.stream.read_exact(&mut buf)).await??;
+        let group = multicast::unpack_group(family[0], &mut Cursor::new(buf))?;
+        let socket = multicast::receiver(group, self.timeout)?;
+        socket.set_nonblocking(true)?;
+        self.multicast.replace((
+            tokio::net::UdpSocket::from_std(socket)?,
+            Reassembler::new(stream_id),
+        ));
+        Ok(stream_info)
+    }
+    async fn open_stream(
+        &mut self,
+        stream_id: u16,
+        max_fps: u8,
+        flags: u8,
+    ) -> Result<StreamInfo, Error> {
+        if self.control {
+            return Err(Error::NotReady);
+        }
+        self.select(stream_id, max_fps, flags).await?;
         let mut buf = [0u8; 7];
         tokio::time::timeout(self.timeout, self.stream.read_exact(&mut buf)).await??;
         let stream_info = StreamInfo::read(&mut Cursor::new(&buf))?;
@@ -59,11 +170,105 @@ impl ClientAsync {
             Err(Error::InvalidStream)
         }
     }
+    /// List server parameters. Parameter methods switch the client into a control session, so
+    /// they can not be used after a stream is selected and vice versa.
+    pub async fn params(&mut self) -> Result<Vec<ParamInfo>, Error> {
+        let response = self.control_request(&ControlRequest::ListParams).await?;
+        let list = ParamList::read(&mut Cursor::new(control_response_payload(&response)?))?;
+        Ok(list.params.into_iter().map(Into::into).collect())
+    }
+    /// List server streams with their names. Uses a control session (see [`Self::params`]).
+    pub async fn streams(&mut self) -> Result<Vec<StreamDescription>, Error> {
+        let response = self.control_request(&ControlRequest::ListStreams).await?;
+        let list = StreamList::read(&mut Cursor::new(control_response_payload(&response)?))?;
+        Ok(list.streams.into_iter().map(Into::into).collect())
+    }
+    /// Get a server parameter value
+    pub async fn param(&mut self, name: &str) -> Result<ParamValue, Error> {
+        let response = self
+            .control_request(&ControlRequest::GetParam(ParamName::new(name)?))
+            .await?;
+        Ok(ParamValue::read(&mut Cursor::new(
+            control_response_payload(&response)?,
+        ))?)
+    }
+    /// Set a server parameter value
+    pub async fn set_param(&mut self, name: &str, value: ParamValue) -> Result<(), Error> {
+        let response = self
+            .control_request(&ControlRequest::SetParam(ParamName::new(name)?, value))
+            .await?;
+        control_response_payload(&response)?;
+        Ok(())
+    }
+    /// Sends STREAM-SELECT (signed if the server requires authentication) and checks the result
+    async fn select(&mut self, stream_id: u16, max_fps: u8, flags: u8) -> Result<(), Error> {
+        let mut buf = pack(&StreamSelect {
+            stream_id,
+            max_fps,
+            flags,
+        })?;
+        if let Some(ref nonce) = self.nonce {
+            buf.extend(auth::sign(self.secret.as_deref(), nonce));
+        }
+        tokio::time::timeout(self.timeout, self.stream.write_all(&buf)).await??;
+        let mut status = [0u8; 1];
+        tokio::time::timeout(self.timeout, self.stream.read_exact(&mut status)).await??;
+        check_select_status(status[0])
+    }
+    async fn control_request(&mut self, request: &ControlRequest) -> Result<Vec<u8>, Error> {
+        if self.ready {
+            return Err(Error::NotReady);
+        }
+        if !self.control {
+            self.select(CONTROL_STREAM_ID, 0, 0).await?;
+            self.control = true;
+        }
+        tokio::time::timeout(
+            self.timeout,
+            self.stream.write_all(&pack_control_request(request)?),
+        )
+        .await??;
+        let mut len_buf = [0u8; 4];
+        tokio::time::timeout(self.timeout, self.stream.read_exact(&mut len_buf)).await??;
+        let len = usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::InvalidParam)?;
+        if len > MAX_CONTROL_RESPONSE_LEN {
+            return Err(Error::InvalidParam);
+        }
+        let mut response = vec![0u8; len];
+        tokio::time::timeout(self.timeout, self.stream.read_exact(&mut response)).await??;
+        Ok(response)
+    }
+    /// Send an application-defined event to the server. The server processes events while the
+    /// stream is active, so the client must have a stream selected.
+    pub async fn send_event(&mut self, data: &[u8]) -> Result<(), Error> {
+        if !self.ready {
+            return Err(Error::NotReady);
+        }
+        if data.len() > MAX_EVENT_SIZE {
+            return Err(Error::EventTooLarge);
+        }
+        let len = u32::try_from(data.len()).map_err(|_| Error::EventTooLarge)?;
+        let mut buf = Vec::with_capacity(data.len() + 5);
+        buf.push(CLIENT_MSG_EVENT);
+        buf.extend(len.to_le_bytes());
+        buf.extend(data);
+        tokio::time::timeout(self.timeout, self.stream.write_all(&buf)).await??;
+        Ok(())
+    }
     /// Read a next frame from the server
     pub async fn read_next(&mut self) -> Result<Frame, Error> {
         if !self.ready {
             return Err(Error::NotReady);
         }
+        if let Some((ref socket, ref mut reassembler)) = self.multicast {
+            let mut buf = [0u8; multicast::DATAGRAM_SIZE];
+            loop {
+                let len = tokio::time::timeout(self.timeout, socket.recv(&mut buf)).await??;
+                if let Some(frame) = reassembler.push(&buf[..len])? {
+                    return Ok(frame);
+                }
+            }
+        }
         let mut len_buf = [0u8; 4];
         tokio::time::timeout(self.timeout, self.stream.read_exact(&mut len_buf)).await??;
         let len = usize::try_from(u32::from_le_bytes(len_buf))
@@ -80,10 +285,16 @@ impl ClientAsync {
             usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::FrameDataTooLarge)?;
         let mut data = vec![0u8; len];
         tokio::time::timeout(self.timeout, self.stream.read_exact(&mut data)).await??;
-        tokio::time::timeout(self.timeout, self.stream.write_all(&[0u8; 1])).await??;
+        tokio::time::timeout(self.timeout, self.stream.write_all(&[CLIENT_MSG_ACK])).await??;
         Ok(Frame {
             metadata: metadata.map(Into::into),
             data: data.into(),
         })
     }
 }
+
+async fn tcp_connect(addr: impl ToSocketAddrs, timeout: Duration) -> Result<TcpStream, Error> {
+    let stream = tokio::time::timeout(timeout, TcpStream::connect(addr)).await??;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
@@ -0,0 +1,206 @@
+//! Pre/post-trigger clips: recent frames of a stream are kept in a ring buffer, a trigger writes
+//! the buffered frames, followed by the next ones, into a recording file
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{trace, warn};
+
+use crate::{continuous::sanitize_file_name, Error, Frame, Recorder, Stream, StreamInfo};
+
+/// A ring buffer of recent stream frames. The capacity is allocated once, buffered frames share
+/// data with the sent ones. Post-trigger frames of pending clips are collected as they are sent,
+/// so they are not evicted from the ring before being written.
+pub(crate) struct ClipBuffer {
+    dir: PathBuf,
+    duration: Duration,
+    max_frames: usize,
+    frames: VecDeque<(Instant, Frame)>,
+    // clip id - post-trigger period end and collected frames
+    pending: BTreeMap<u64, (Instant, Vec<(Instant, Frame)>)>,
+    next_clip_id: u64,
+}
+
+impl ClipBuffer {
+    pub(crate) fn new(dir: &Path, duration: Duration, max_frames: usize) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            duration,
+            max_frames: max_frames.max(1),
+            frames: VecDeque::with_capacity(max_frames.max(1)),
+            pending: BTreeMap::new(),
+            next_clip_id: 0,
+        }
+    }
+    /// Called by the server for each sent frame
+    pub(crate) fn push(&mut self, frame: &Frame) {
+        let now = Instant::now();
+        while self.frames.len() >= self.max_frames
+            || self
+                .frames
+                .front()
+                .map_or(false, |(t, _)| now.duration_since(*t) > self.duration)
+        {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((now, frame.clone()));
+        for (end, frames) in self.pending.values_mut() {
+            if now <= *end && frames.len() < self.max_frames {
+                frames.push((now, frame.clone()));
+            }
+        }
+    }
+    pub(crate) fn dir(&self) -> &Path {
+        &self.dir
+    }
+    pub(crate) fn duration(&self) -> Duration {
+        self.duration
+    }
+    /// Buffered frames, sent within the period
+    pub(crate) fn frames_between(&self, from: Instant, to: Instant) -> Vec<(Instant, Frame)> {
+        self.frames
+            .iter()
+            .filter(|(t, _)| *t >= from && *t <= to)
+            .cloned()
+            .collect()
+    }
+    /// Starts collecting frames, sent until the end, returns the clip id
+    pub(crate) fn start_post_frames(&mut self, end: Instant) -> u64 {
+        let clip_id = self.next_clip_id;
+        self.next_clip_id += 1;
+        self.pending.insert(clip_id, (end, Vec::new()));
+        clip_id
+    }
+    /// Takes collected post-trigger frames of the clip
+    pub(crate) fn take_post_frames(&mut self, clip_id: u64) -> Vec<(Instant, Frame)> {
+        self.pending
+            .remove(&clip_id)
+            .map(|(_, frames)| frames)
+            .unwrap_or_default()
+    }
+}
+
+pub(crate) struct Clip {
+    pub(crate) path: PathBuf,
+    pub(crate) info: StreamInfo,
+    pub(crate) name: Option<String>,
+    pub(crate) start: Instant,
+    pub(crate) end: Instant,
+    pub(crate) pre_frames: Vec<(Instant, Frame)>,
+    pub(crate) clip_id: u64,
+}
+
+impl Clip {
+    pub(crate) fn path_for(dir: &Path, label: &str) -> PathBuf {
+        dir.join(format!(
+            "{}_{:020}.rvr",
+            sanitize_file_name(label),
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros()
+        ))
+    }
+    /// Starts a background worker, which waits for the post-trigger frames and writes the clip
+    pub(crate) fn spawn(self, stream: Stream) {
+        thread::spawn(move || {
+            thread::sleep(self.end.saturating_duration_since(Instant::now()));
+            let post_frames = stream
+                .server_inner
+                .take_clip_post_frames(stream.id, self.clip_id);
+            match self.write(post_frames) {
+                Ok(frames) => {
+                    trace!(stream_id = stream.id, path = %self.path.display(), frames, "clip written");
+                }
+                Err(error) => {
+                    warn!(stream_id = stream.id, path = %self.path.display(), %error,
+                        "unable to write clip");
+                }
+            }
+        });
+    }
+    fn write(&self, post_frames: Vec<(Instant, Frame)>) -> Result<usize, Error> {
+        let mut recorder = Recorder::create(&self.path, &self.info, self.name.as_deref())?;
+        for (t, frame) in self.pre_frames.iter().chain(post_frames.iter()) {
+            recorder.write_frame_at(frame, t.duration_since(self.start))?;
+        }
+        let frames = recorder.frame_count();
+        recorder.finish()?;
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::Path,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::ClipBuffer;
+    use crate::Frame;
+
+    fn frame(n: u8) -> Frame {
+        Frame::from(vec![n])
+    }
+
+    fn numbers(frames: &[(Instant, Frame)]) -> Vec<u8> {
+        frames.iter().map(|(_, frame)| frame.data[0]).collect()
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut buffer = ClipBuffer::new(Path::new("."), Duration::from_secs(60), 3);
+        let start = Instant::now();
+        for n in 0..5 {
+            buffer.push(&frame(n));
+        }
+        assert_eq!(
+            numbers(&buffer.frames_between(start, Instant::now())),
+            [2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_pre_post() {
+        let mut buffer = ClipBuffer::new(Path::new("."), Duration::from_secs(60), 100);
+        let start = Instant::now();
+        buffer.push(&frame(0));
+        thread::sleep(Duration::from_millis(10));
+        let pre_start = Instant::now();
+        buffer.push(&frame(1));
+        buffer.push(&frame(2));
+        let trigger = Instant::now();
+        assert_eq!(numbers(&buffer.frames_between(pre_start, trigger)), [1, 2]);
+        assert_eq!(numbers(&buffer.frames_between(start, trigger)), [0, 1, 2]);
+        let clip_id = buffer.start_post_frames(trigger + Duration::from_millis(50));
+        buffer.push(&frame(3));
+        buffer.push(&frame(4));
+        thread::sleep(Duration::from_millis(60));
+        // sent after the post-trigger period end
+        buffer.push(&frame(5));
+        assert_eq!(numbers(&buffer.take_post_frames(clip_id)), [3, 4]);
+        assert!(buffer.take_post_frames(clip_id).is_empty());
+    }
+
+    #[test]
+    fn test_post_evicted() {
+        let mut buffer = ClipBuffer::new(Path::new("."), Duration::from_secs(60), 2);
+        let start = Instant::now();
+        let clip_id = buffer.start_post_frames(start + Duration::from_secs(60));
+        for n in 0..3 {
+            buffer.push(&frame(n));
+        }
+        assert_eq!(
+            numbers(&buffer.frames_between(start, Instant::now())),
+            [1, 2]
+        );
+        // the first frame is kept for the clip, the number of post-trigger frames is limited by
+        // the max number of buffered frames
+        assert_eq!(numbers(&buffer.take_post_frames(clip_id)), [0, 1]);
+    }
+}
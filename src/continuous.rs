@@ -0,0 +1,395 @@
+//! Continuous recording: a stream is written into segmented recording files by a background
+//! worker, which receives frames as a local client
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{trace, warn};
+
+use crate::{Error, Recorder, Stream};
+
+const SEGMENT_EXTENSION: &str = "rvr";
+
+const DEFAULT_SEGMENT_DURATION: Duration = Duration::from_secs(600);
+
+/// How often the current segment is checked for rotation if the stream is idle
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Continuous recording settings: where segments are written, when they are rotated and which
+/// ones are deleted. By default, segments are rotated every 10 minutes and never deleted.
+#[derive(Clone, Debug)]
+pub struct ContinuousRecording {
+    dir: PathBuf,
+    prefix: Option<String>,
+    segment_size: Option<u64>,
+    segment_duration: Option<Duration>,
+    max_age: Option<Duration>,
+    max_total_size: Option<u64>,
+}
+
+impl ContinuousRecording {
+    /// Create new settings to write segments into the directory (created if missing)
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            prefix: None,
+            segment_size: None,
+            segment_duration: Some(DEFAULT_SEGMENT_DURATION),
+            max_age: None,
+            max_total_size: None,
+        }
+    }
+    /// Segment file name prefix (default: the stream name or `stream<ID>`). Segments are named
+    /// as `<prefix>_<start time in microseconds since UNIX epoch>.rvr`.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
+        self
+    }
+    /// Start a new segment when the current one reaches the size (in bytes)
+    pub fn segment_size(mut self, size: u64) -> Self {
+        self.segment_size = Some(size);
+        self
+    }
+    /// Start a new segment when the current one reaches the duration (`None` - do not rotate
+    /// segments by time)
+    pub fn segment_duration(mut self, duration: Option<Duration>) -> Self {
+        self.segment_duration = duration;
+        self
+    }
+    /// Delete segments, which have not been modified for longer than the given time (checked
+    /// when the recording and each new segment are started)
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+    /// Delete the oldest segments when the total size of the segments exceeds the quota (in
+    /// bytes). Segments are checked when the recording and each new segment are started, so the
+    /// total size can exceed the quota by the size of the current segment, which is never
+    /// deleted.
+    pub fn max_total_size(mut self, size: u64) -> Self {
+        self.max_total_size = Some(size);
+        self
+    }
+}
+
+struct Segment {
+    recorder: Recorder,
+    path: PathBuf,
+    started: Instant,
+}
+
+/// A continuous recording helper object, returned by [`Stream::record`]
+pub struct ContinuousRecorder {
+    stream: Stream,
+    client_id: usize,
+}
+
+impl ContinuousRecorder {
+    pub(crate) fn spawn(config: ContinuousRecording, stream: Stream) -> Result<Self, Error> {
+        fs::create_dir_all(&config.dir)?;
+        let info = stream.server_inner.stream_info(stream.id)?;
+        let prefix = config.prefix.clone().unwrap_or_else(|| {
+            stream.name().map_or_else(
+                || format!("stream{}", stream.id),
+                |name| sanitize_file_name(&name),
+            )
+        });
+        // segments, left by previous recordings, are checked before the first frame arrives
+        enforce_retention(&config, &prefix, None);
+        let client_id = stream.server_inner.next_client_id();
+        let rx = stream.server_inner.add_client(stream.id, client_id, None)?;
+        let name = stream.name();
+        thread::spawn(move || {
+            let mut segment: Option<Segment> = None;
+            loop {
+                let frame = match rx.get_timeout(CHECK_INTERVAL) {
+                    Ok(frame) => Some(frame),
+                    Err(rtsc::Error::Timeout) => None,
+                    // the recording is stopped
+                    Err(_) => break,
+                };
+                if segment.as_ref().map_or(false, |s| {
+                    config
+                        .segment_duration
+                        .map_or(false, |d| s.started.elapsed() >= d)
+                        || config
+                            .segment_size
+                            .map_or(false, |size| s.recorder.size() >= size)
+                }) {
+                    close_segment(segment.take().unwrap(), info.id);
+                }
+                let Some(frame) = frame else {
+                    continue;
+                };
+                if segment.is_none() {
+                    let path = config.dir.join(format!(
+                        "{}_{:020}.{}",
+                        prefix,
+                        SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_micros(),
+                        SEGMENT_EXTENSION
+                    ));
+                    match Recorder::create(&path, &info, name.as_deref()) {
+                        Ok(recorder) => {
+                            trace!(stream_id = info.id, path = %path.display(), "segment started");
+                            segment.replace(Segment {
+                                recorder,
+                                path,
+                                started: Instant::now(),
+                            });
+                            enforce_retention(&config, &prefix, segment.as_ref());
+                        }
+                        Err(error) => {
+                            warn!(stream_id = info.id, path = %path.display(), %error,
+                                "unable to create segment");
+                            continue;
+                        }
+                    }
+                }
+                let current = segment.as_mut().unwrap();
+                if let Err(error) = current.recorder.write_frame(&frame) {
+                    warn!(stream_id = info.id, path = %current.path.display(), %error,
+                        "unable to write segment");
+                    // the segment is closed, the next frame starts a new one
+                    close_segment(segment.take().unwrap(), info.id);
+                }
+            }
+            if let Some(segment) = segment {
+                close_segment(segment, info.id);
+            }
+            trace!(stream_id = info.id, "continuous recording stopped");
+        });
+        Ok(Self { stream, client_id })
+    }
+    /// Stop the recording. The current segment is finished by the worker.
+    pub fn stop(self) {
+        self.stream
+            .server_inner
+            .remove_client(self.stream.id, self.client_id);
+    }
+}
+
+fn close_segment(segment: Segment, stream_id: u16) {
+    if let Err(error) = segment.recorder.finish() {
+        warn!(stream_id, path = %segment.path.display(), %error, "unable to finish segment");
+    } else {
+        trace!(stream_id, path = %segment.path.display(), "segment finished");
+    }
+}
+
+/// Deletes segments, which are too old or do not fit the quota (the oldest ones first)
+fn enforce_retention(config: &ContinuousRecording, prefix: &str, current: Option<&Segment>) {
+    if config.max_age.is_none() && config.max_total_size.is_none() {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(&config.dir) else {
+        return;
+    };
+    let segment_prefix = format!("{}_", prefix);
+    let mut segments: Vec<(PathBuf, u64, Option<SystemTime>)> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            is_segment_name(&entry.file_name().to_string_lossy(), &segment_prefix)
+                && current.map_or(true, |current| current.path != entry.path())
+        })
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()))
+        })
+        .collect();
+    // names contain zero-padded start times, so the oldest segments go first
+    segments.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut total_size: u64 = segments.iter().map(|s| s.1).sum::<u64>()
+        + current.map_or(0, |current| current.recorder.size());
+    for (path, size, modified) in segments {
+        let expired = config.max_age.map_or(false, |max_age| {
+            modified
+                .and_then(|m| m.elapsed().ok())
+                .map_or(false, |age| age > max_age)
+        });
+        let over_quota = config
+            .max_total_size
+            .map_or(false, |quota| total_size > quota);
+        if !expired && !over_quota {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                trace!(path = %path.display(), "segment deleted");
+                total_size -= size;
+            }
+            Err(error) => warn!(path = %path.display(), %error, "unable to delete segment"),
+        }
+    }
+}
+
+fn is_segment_name(name: &str, segment_prefix: &str) -> bool {
+    name.strip_prefix(segment_prefix)
+        .and_then(|name| name.strip_suffix(SEGMENT_EXTENSION))
+        .and_then(|name| name.strip_suffix('.'))
+        .map_or(false, |start| {
+            !start.is_empty() && start.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+pub(crate) fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        thread,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use super::{is_segment_name, ContinuousRecording};
+    use crate::{Format, Frame, RecordingReader, Server, Stream};
+
+    const WIDTH: u16 = 40;
+    const HEIGHT: u16 = 25;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rvideo-test-{}-{}", std::process::id(), name));
+        let _r = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Frames of segments, in the segment order (0 for segments, which are not readable yet)
+    fn segment_frames(dir: &Path) -> Vec<usize> {
+        file_names(dir)
+            .iter()
+            .filter(|name| is_segment_name(name, "cam_"))
+            .map(|name| {
+                RecordingReader::open(dir.join(name)).map_or(0, |reader| reader.frame_count())
+            })
+            .collect()
+    }
+
+    fn send_frames(stream: &Stream, frames: u8, interval: Duration) {
+        for n in 0..frames {
+            stream
+                .send_frame(Frame::from(vec![
+                    n;
+                    usize::from(WIDTH) * usize::from(HEIGHT)
+                ]))
+                .unwrap();
+            thread::sleep(interval);
+        }
+    }
+
+    /// Waits until the worker has written all frames
+    fn wait_frames(dir: &Path, frames: usize) {
+        let started = Instant::now();
+        while segment_frames(dir).iter().sum::<usize>() < frames {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "frames not written"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = temp_dir("continuous-size");
+        let server = Server::new(Duration::from_secs(1));
+        let stream = server.add_stream(Format::Luma8, WIDTH, HEIGHT).unwrap();
+        // a segment is rotated after each frame, a finished one takes about 1 KiB, so two
+        // finished segments fit the quota
+        let recorder = stream
+            .record(
+                ContinuousRecording::new(&dir)
+                    .prefix("cam")
+                    .segment_size(1)
+                    .segment_duration(None)
+                    .max_total_size(2500),
+            )
+            .unwrap();
+        send_frames(&stream, 5, Duration::from_millis(20));
+        recorder.stop();
+        wait_frames(&dir, 3);
+        // the oldest segments are deleted
+        assert_eq!(segment_frames(&dir), [1, 1, 1]);
+        let frames: Vec<u8> = file_names(&dir)
+            .iter()
+            .map(|name| {
+                let mut reader = RecordingReader::open(dir.join(name)).unwrap();
+                reader.read_frame().unwrap().unwrap().frame.data[0]
+            })
+            .collect();
+        assert_eq!(frames, [2, 3, 4]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotate_by_time() {
+        let dir = temp_dir("continuous-time");
+        // left by a previous recording
+        let old = dir.join(format!("cam_{:020}.rvr", 1));
+        fs::write(&old, b"old").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&old)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+        let recent = dir.join(format!("cam_{:020}.rvr", 2));
+        fs::write(&recent, b"recent").unwrap();
+        // not a segment
+        fs::write(dir.join("cam_old.rvr"), b"other").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(dir.join("cam_old.rvr"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+        let server = Server::new(Duration::from_secs(1));
+        let stream = server.add_stream(Format::Luma8, WIDTH, HEIGHT).unwrap();
+        let recorder = stream
+            .record(
+                ContinuousRecording::new(&dir)
+                    .prefix("cam")
+                    .segment_duration(Some(Duration::from_millis(100)))
+                    .max_age(Duration::from_secs(60)),
+            )
+            .unwrap();
+        // expired segments are deleted when the recording is started
+        assert!(!old.exists());
+        assert!(recent.exists());
+        fs::remove_file(&recent).unwrap();
+        send_frames(&stream, 15, Duration::from_millis(20));
+        recorder.stop();
+        wait_frames(&dir, 15);
+        let segments = segment_frames(&dir);
+        assert!(segments.len() >= 2, "{:?}", segments);
+        assert!(segments.iter().all(|frames| *frames > 0));
+        assert!(dir.join("cam_old.rvr").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use jpeg_encoder::{ColorType, Encoder};
+
+use super::{check_raw_frame, FrameWriter};
+use crate::{Error, Format, Frame, StreamInfo};
+
+const DEFAULT_QUALITY: u8 = 90;
+
+/// Used if the frame rate can not be calculated (less than two frames exported)
+const DEFAULT_FPS: f64 = 25.0;
+
+const AVIF_HASINDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+const CHUNK_ID: &[u8; 4] = b"00dc";
+
+// header field offsets, updated when the file is finished
+const RIFF_SIZE_OFFSET: u64 = 4;
+const AVIH_MICROSEC_PER_FRAME_OFFSET: u64 = 32;
+const AVIH_MAX_BYTES_PER_SEC_OFFSET: u64 = 36;
+const AVIH_TOTAL_FRAMES_OFFSET: u64 = 48;
+const AVIH_SUGGESTED_BUFFER_SIZE_OFFSET: u64 = 60;
+const STRH_SCALE_OFFSET: u64 = 128;
+const STRH_RATE_OFFSET: u64 = 132;
+const STRH_LENGTH_OFFSET: u64 = 140;
+const STRH_SUGGESTED_BUFFER_SIZE_OFFSET: u64 = 144;
+const MOVI_SIZE_OFFSET: u64 = 216;
+/// Offsets in the index are relative to the "movi" list type
+const MOVI_OFFSET: u64 = 220;
+const HEADER_SIZE: u64 = 224;
+
+/// Frame rates are written as `rate / RATE_SCALE`
+const RATE_SCALE: u32 = 1000;
+
+struct IndexEntry {
+    offset: u32,
+    size: u32,
+}
+
+/// Writes frames into an AVI (RIFF, AVI 1.0) file with a single Motion JPEG video stream. MJPEG
+/// frames are written as-is, raw frames are encoded into JPEG (16-bit formats are reduced to
+/// 8-bit, alpha channels are dropped). The frame rate is calculated from frame timestamps when
+/// the file is finished. The file size is limited to 4 GB.
+pub struct AviWriter {
+    file: BufWriter<File>,
+    format: Format,
+    width: u16,
+    height: u16,
+    quality: u8,
+    position: u64,
+    index: Vec<IndexEntry>,
+    max_frame_size: u32,
+    first_timestamp: Option<Duration>,
+    last_timestamp: Duration,
+    finished: bool,
+}
+
+impl AviWriter {
+    /// Create a new AVI file for the stream
+    pub fn create(path: impl AsRef<Path>, info: &StreamInfo) -> Result<Self, Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header(info.width, info.height))?;
+        Ok(Self {
+            file,
+            format: info.format,
+            width: info.width,
+            height: info.height,
+            quality: DEFAULT_QUALITY,
+            position: HEADER_SIZE,
+            index: Vec::new(),
+            max_frame_size: 0,
+            first_timestamp: None,
+            last_timestamp: Duration::ZERO,
+            finished: false,
+        })
+    }
+    /// JPEG quality (1-100, default: 90) for raw frames
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality.clamp(1, 100);
+        self
+    }
+    /// Number of frames written
+    pub fn frame_count(&self) -> usize {
+        self.index.len()
+    }
+    fn encode<'a>(&self, data: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        if self.format == Format::MJpeg {
+            return Ok(Cow::Borrowed(data));
+        }
+        check_raw_frame(self.format, self.width, self.height, data)?;
+        let (pixels, color_type): (Cow<[u8]>, ColorType) = match self.format {
+            Format::Luma8 => (Cow::Borrowed(data), ColorType::Luma),
+            Format::Rgb8 => (Cow::Borrowed(data), ColorType::Rgb),
+            // the alpha channel is ignored by the encoder
+            Format::Rgba8 => (Cow::Borrowed(data), ColorType::Rgba),
+            // keep the luma byte (or the high byte of little-endian 16-bit samples) only
+            Format::LumaA8 => (select_bytes(data, 2, &[0]), ColorType::Luma),
+            Format::Luma16 => (select_bytes(data, 2, &[1]), ColorType::Luma),
+            Format::LumaA16 => (select_bytes(data, 4, &[1]), ColorType::Luma),
+            Format::Rgb16 => (select_bytes(data, 6, &[1, 3, 5]), ColorType::Rgb),
+            Format::Rgba16 => (select_bytes(data, 8, &[1, 3, 5]), ColorType::Rgb),
+            Format::MJpeg => unreachable!(),
+        };
+        let mut buf = Vec::new();
+        Encoder::new(&mut buf, self.quality)
+            .encode(&pixels, self.width, self.height, color_type)
+            .map_err(|_| Error::InvalidFrame)?;
+        Ok(Cow::Owned(buf))
+    }
+    fn patch(&mut self, offset: u64, value: u32) -> Result<(), Error> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&value.to_le_bytes())?;
+        Ok(())
+    }
+    fn fps(&self) -> f64 {
+        let frames = self.index.len();
+        let duration = self
+            .first_timestamp
+            .map_or(Duration::ZERO, |first| self.last_timestamp - first);
+        if frames < 2 || duration.is_zero() {
+            DEFAULT_FPS
+        } else {
+            (frames - 1) as f64 / duration.as_secs_f64()
+        }
+    }
+}
+
+impl FrameWriter for AviWriter {
+    fn write_frame(&mut self, frame: &Frame, timestamp: Duration) -> Result<(), Error> {
+        if self.finished {
+            return Err(Error::NotReady);
+        }
+        let data = self.encode(&frame.data)?;
+        let size = u32::try_from(data.len()).map_err(|_| Error::FrameDataTooLarge)?;
+        let padded = u64::from(size) + u64::from(size % 2);
+        // the chunk and the index entry of this and all previous frames must fit the file
+        let index_size = 8 + 16 * (self.index.len() as u64 + 1);
+        if self.position + 8 + padded + index_size > u64::from(u32::MAX) {
+            return Err(io::Error::new(io::ErrorKind::Other, "AVI file size limit reached").into());
+        }
+        self.file.write_all(CHUNK_ID)?;
+        self.file.write_all(&size.to_le_bytes())?;
+        self.file.write_all(&data)?;
+        if size % 2 == 1 {
+            self.file.write_all(&[0])?;
+        }
+        self.index.push(IndexEntry {
+            offset: u32::try_from(self.position - MOVI_OFFSET).unwrap(),
+            size,
+        });
+        self.position += 8 + padded;
+        self.max_frame_size = self.max_frame_size.max(size);
+        self.first_timestamp.get_or_insert(timestamp);
+        self.last_timestamp = timestamp;
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        let movi_size = u32::try_from(self.position - MOVI_OFFSET).unwrap();
+        let mut idx1 = Vec::with_capacity(8 + 16 * self.index.len());
+        idx1.extend(b"idx1");
+        idx1.extend(u32::try_from(16 * self.index.len()).unwrap().to_le_bytes());
+        for entry in &self.index {
+            idx1.extend(CHUNK_ID);
+            idx1.extend(AVIIF_KEYFRAME.to_le_bytes());
+            idx1.extend(entry.offset.to_le_bytes());
+            idx1.extend(entry.size.to_le_bytes());
+        }
+        self.file.write_all(&idx1)?;
+        let file_size = self.position + idx1.len() as u64;
+        let fps = self.fps();
+        let frames = u32::try_from(self.index.len()).unwrap();
+        let bytes_per_sec = (f64::from(self.max_frame_size) * fps).min(f64::from(u32::MAX));
+        self.patch(RIFF_SIZE_OFFSET, u32::try_from(file_size - 8).unwrap())?;
+        self.patch(
+            AVIH_MICROSEC_PER_FRAME_OFFSET,
+            (1_000_000.0 / fps).round() as u32,
+        )?;
+        self.patch(AVIH_MAX_BYTES_PER_SEC_OFFSET, bytes_per_sec as u32)?;
+        self.patch(AVIH_TOTAL_FRAMES_OFFSET, frames)?;
+        self.patch(AVIH_SUGGESTED_BUFFER_SIZE_OFFSET, self.max_frame_size)?;
+        self.patch(STRH_SCALE_OFFSET, RATE_SCALE)?;
+        self.patch(
+            STRH_RATE_OFFSET,
+            ((fps * f64::from(RATE_SCALE)).round() as u32).max(1),
+        )?;
+        self.patch(STRH_LENGTH_OFFSET, frames)?;
+        self.patch(STRH_SUGGESTED_BUFFER_SIZE_OFFSET, self.max_frame_size)?;
+        self.patch(MOVI_SIZE_OFFSET, movi_size)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for AviWriter {
+    fn drop(&mut self) {
+        let _r = self.finish();
+    }
+}
+
+/// Picks bytes at the given positions from each pixel
+fn select_bytes<'a>(data: &[u8], bpp: usize, positions: &[usize]) -> Cow<'a, [u8]> {
+    Cow::Owned(
+        data.chunks_exact(bpp)
+            .flat_map(|pixel| positions.iter().map(|&i| pixel[i]))
+            .collect(),
+    )
+}
+
+/// AVI headers with placeholders for values, which are known when the file is finished
+fn header(width: u16, height: u16) -> Vec<u8> {
+    fn u32le(buf: &mut Vec<u8>, value: u32) {
+        buf.extend(value.to_le_bytes());
+    }
+    fn u16le(buf: &mut Vec<u8>, value: u16) {
+        buf.extend(value.to_le_bytes());
+    }
+    let width32 = u32::from(width);
+    let height32 = u32::from(height);
+    let mut h = Vec::with_capacity(usize::try_from(HEADER_SIZE).unwrap());
+    h.extend(b"RIFF");
+    u32le(&mut h, 0); // file size - 8
+    h.extend(b"AVI ");
+    h.extend(b"LIST");
+    u32le(&mut h, 192); // hdrl list size
+    h.extend(b"hdrl");
+    // main header
+    h.extend(b"avih");
+    u32le(&mut h, 56);
+    u32le(&mut h, 0); // microseconds per frame
+    u32le(&mut h, 0); // max bytes per second
+    u32le(&mut h, 0); // padding granularity
+    u32le(&mut h, AVIF_HASINDEX);
+    u32le(&mut h, 0); // total frames
+    u32le(&mut h, 0); // initial frames
+    u32le(&mut h, 1); // streams
+    u32le(&mut h, 0); // suggested buffer size
+    u32le(&mut h, width32);
+    u32le(&mut h, height32);
+    h.extend([0u8; 16]); // reserved
+    h.extend(b"LIST");
+    u32le(&mut h, 116); // strl list size
+    h.extend(b"strl");
+    // stream header
+    h.extend(b"strh");
+    u32le(&mut h, 56);
+    h.extend(b"vids");
+    h.extend(b"MJPG");
+    u32le(&mut h, 0); // flags
+    u16le(&mut h, 0); // priority
+    u16le(&mut h, 0); // language
+    u32le(&mut h, 0); // initial frames
+    u32le(&mut h, 0); // scale
+    u32le(&mut h, 0); // rate
+    u32le(&mut h, 0); // start
+    u32le(&mut h, 0); // length
+    u32le(&mut h, 0); // suggested buffer size
+    u32le(&mut h, u32::MAX); // quality (default)
+    u32le(&mut h, 0); // sample size
+    u16le(&mut h, 0); // frame rectangle
+    u16le(&mut h, 0);
+    u16le(&mut h, width);
+    u16le(&mut h, height);
+    // stream format (BITMAPINFOHEADER)
+    h.extend(b"strf");
+    u32le(&mut h, 40);
+    u32le(&mut h, 40);
+    u32le(&mut h, width32);
+    u32le(&mut h, height32);
+    u16le(&mut h, 1); // planes
+    u16le(&mut h, 24); // bits per pixel
+    h.extend(b"MJPG");
+    u32le(&mut h, width32.saturating_mul(height32).saturating_mul(3)); // image size
+    u32le(&mut h, 0); // pixels per meter (x)
+    u32le(&mut h, 0); // pixels per meter (y)
+    u32le(&mut h, 0); // colors used
+    u32le(&mut h, 0); // colors important
+    h.extend(b"LIST");
+    u32le(&mut h, 0); // movi list size
+    h.extend(b"movi");
+    debug_assert_eq!(h.len() as u64, HEADER_SIZE);
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::{
+        AviWriter, AVIH_MICROSEC_PER_FRAME_OFFSET, AVIH_TOTAL_FRAMES_OFFSET, CHUNK_ID, HEADER_SIZE,
+        MOVI_OFFSET, MOVI_SIZE_OFFSET, RIFF_SIZE_OFFSET, STRH_LENGTH_OFFSET, STRH_RATE_OFFSET,
+        STRH_SCALE_OFFSET,
+    };
+    use crate::{export::FrameWriter, Format, Frame, StreamInfo};
+
+    fn u32_at(data: &[u8], offset: u64) -> u32 {
+        let offset = usize::try_from(offset).unwrap();
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_header_and_index() {
+        let path = std::env::temp_dir().join(format!("rvideo-test-{}.avi", std::process::id()));
+        let info = StreamInfo {
+            id: 0,
+            format: Format::MJpeg,
+            width: 4,
+            height: 2,
+        };
+        // odd sizes are padded in the file
+        let frames: Vec<Vec<u8>> = vec![vec![1; 5], vec![2; 8], vec![3; 3]];
+        let mut writer = AviWriter::create(&path, &info).unwrap();
+        for (i, data) in frames.iter().enumerate() {
+            let frame = Frame::from(data.clone());
+            writer
+                .write_frame(&frame, Duration::from_millis(100 * i as u64))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        drop(writer);
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(&data[8..12], b"AVI ");
+        assert_eq!(u32_at(&data, RIFF_SIZE_OFFSET) as usize, data.len() - 8);
+        assert_eq!(&data[MOVI_OFFSET as usize..HEADER_SIZE as usize], b"movi");
+        assert_eq!(u32_at(&data, AVIH_TOTAL_FRAMES_OFFSET), 3);
+        assert_eq!(u32_at(&data, STRH_LENGTH_OFFSET), 3);
+        assert_eq!(u32_at(&data, AVIH_MICROSEC_PER_FRAME_OFFSET), 100_000);
+        assert_eq!(
+            u32_at(&data, STRH_RATE_OFFSET) / u32_at(&data, STRH_SCALE_OFFSET),
+            10
+        );
+        let movi_size = u64::from(u32_at(&data, MOVI_SIZE_OFFSET));
+        // chunks: 8 + 6, 8 + 8, 8 + 4
+        assert_eq!(movi_size, 4 + 14 + 16 + 12);
+        let idx1 = usize::try_from(MOVI_OFFSET + movi_size).unwrap();
+        assert_eq!(&data[idx1..idx1 + 4], b"idx1");
+        assert_eq!(u32_at(&data, idx1 as u64 + 4), 16 * 3);
+        assert_eq!(data.len(), idx1 + 8 + 16 * 3);
+        for (i, expected) in frames.iter().enumerate() {
+            let entry = (idx1 + 8 + 16 * i) as u64;
+            assert_eq!(&data[entry as usize..entry as usize + 4], CHUNK_ID);
+            let offset = u64::from(u32_at(&data, entry + 8));
+            let size = u32_at(&data, entry + 12) as usize;
+            assert_eq!(size, expected.len());
+            // index offsets point to chunk headers, relative to the "movi" list type
+            let chunk = usize::try_from(MOVI_OFFSET + offset).unwrap();
+            assert_eq!(&data[chunk..chunk + 4], CHUNK_ID);
+            assert_eq!(u32_at(&data, chunk as u64 + 4) as usize, size);
+            assert_eq!(&data[chunk + 8..chunk + 8 + size], &expected[..]);
+        }
+    }
+}
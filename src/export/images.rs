@@ -0,0 +1,159 @@
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use super::{check_raw_frame, FrameWriter};
+use crate::{Error, Format, Frame, StreamInfo};
+
+/// Image file format of [`ImageSequenceWriter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// PNG (all raw formats)
+    Png,
+    /// TIFF (raw formats without alpha-luma ones)
+    Tiff,
+}
+
+impl ImageFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tiff",
+        }
+    }
+}
+
+/// Writes frames of raw streams into a directory as lossless image files, named as
+/// `frame_<NUMBER>.<EXTENSION>`. 16-bit formats are written as 16-bit images.
+pub struct ImageSequenceWriter {
+    dir: PathBuf,
+    image_format: ImageFormat,
+    format: Format,
+    width: u16,
+    height: u16,
+    frame_number: usize,
+}
+
+impl ImageSequenceWriter {
+    /// Create a new writer for the stream. The directory is created if missing.
+    pub fn create(
+        dir: impl AsRef<Path>,
+        info: &StreamInfo,
+        image_format: ImageFormat,
+    ) -> Result<Self, Error> {
+        let supported = match image_format {
+            ImageFormat::Png => info.format != Format::MJpeg,
+            ImageFormat::Tiff => !matches!(
+                info.format,
+                Format::MJpeg | Format::LumaA8 | Format::LumaA16
+            ),
+        };
+        if !supported {
+            return Err(Error::UnsupportedFormat(info.format));
+        }
+        fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_owned(),
+            image_format,
+            format: info.format,
+            width: info.width,
+            height: info.height,
+            frame_number: 0,
+        })
+    }
+    /// Number of frames written
+    pub fn frame_count(&self) -> usize {
+        self.frame_number
+    }
+    fn write_png(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        let (color_type, bit_depth) = match self.format {
+            Format::Luma8 => (png::ColorType::Grayscale, png::BitDepth::Eight),
+            Format::Luma16 => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+            Format::LumaA8 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+            Format::LumaA16 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+            Format::Rgb8 => (png::ColorType::Rgb, png::BitDepth::Eight),
+            Format::Rgb16 => (png::ColorType::Rgb, png::BitDepth::Sixteen),
+            Format::Rgba8 => (png::ColorType::Rgba, png::BitDepth::Eight),
+            Format::Rgba16 => (png::ColorType::Rgba, png::BitDepth::Sixteen),
+            Format::MJpeg => unreachable!(),
+        };
+        let mut encoder = png::Encoder::new(
+            BufWriter::new(File::create(path)?),
+            self.width.into(),
+            self.height.into(),
+        );
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        let mut writer = encoder.write_header().map_err(png_error)?;
+        if bit_depth == png::BitDepth::Sixteen {
+            // PNG samples are big-endian
+            let swapped: Vec<u8> = data
+                .chunks_exact(2)
+                .flat_map(|sample| [sample[1], sample[0]])
+                .collect();
+            writer.write_image_data(&swapped).map_err(png_error)?;
+        } else {
+            writer.write_image_data(data).map_err(png_error)?;
+        }
+        writer.finish().map_err(png_error)?;
+        Ok(())
+    }
+    fn write_tiff(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        use tiff::encoder::{colortype, TiffEncoder};
+        let mut encoder =
+            TiffEncoder::new(BufWriter::new(File::create(path)?)).map_err(tiff_error)?;
+        let (width, height) = (self.width.into(), self.height.into());
+        let samples16 = || -> Vec<u16> {
+            data.chunks_exact(2)
+                .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+                .collect()
+        };
+        match self.format {
+            Format::Luma8 => encoder.write_image::<colortype::Gray8>(width, height, data),
+            Format::Luma16 => encoder.write_image::<colortype::Gray16>(width, height, &samples16()),
+            Format::Rgb8 => encoder.write_image::<colortype::RGB8>(width, height, data),
+            Format::Rgb16 => encoder.write_image::<colortype::RGB16>(width, height, &samples16()),
+            Format::Rgba8 => encoder.write_image::<colortype::RGBA8>(width, height, data),
+            Format::Rgba16 => encoder.write_image::<colortype::RGBA16>(width, height, &samples16()),
+            Format::LumaA8 | Format::LumaA16 | Format::MJpeg => unreachable!(),
+        }
+        .map_err(tiff_error)
+    }
+}
+
+impl FrameWriter for ImageSequenceWriter {
+    fn write_frame(&mut self, frame: &Frame, _timestamp: Duration) -> Result<(), Error> {
+        check_raw_frame(self.format, self.width, self.height, &frame.data)?;
+        let path = self.dir.join(format!(
+            "frame_{:06}.{}",
+            self.frame_number,
+            self.image_format.extension()
+        ));
+        match self.image_format {
+            ImageFormat::Png => self.write_png(&path, &frame.data)?,
+            ImageFormat::Tiff => self.write_tiff(&path, &frame.data)?,
+        }
+        self.frame_number += 1;
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+fn png_error(error: png::EncodingError) -> Error {
+    match error {
+        png::EncodingError::IoError(error) => error.into(),
+        _ => Error::InvalidFrame,
+    }
+}
+
+fn tiff_error(error: tiff::TiffError) -> Error {
+    match error {
+        tiff::TiffError::IoError(error) => error.into(),
+        _ => Error::InvalidFrame,
+    }
+}
@@ -0,0 +1,99 @@
+//! Stream exports into common file formats, from live clients or recordings (requires the
+//! `export` feature)
+use std::time::{Duration, Instant};
+
+use crate::{Client, Error, Format, Frame, RecordingReader};
+
+mod avi;
+mod images;
+mod sidecar;
+mod y4m;
+
+pub use avi::AviWriter;
+pub use images::{ImageFormat, ImageSequenceWriter};
+pub use sidecar::MetadataSidecar;
+pub use y4m::Y4mWriter;
+
+/// A file format writer, which frames are exported into
+pub trait FrameWriter {
+    /// Write a frame. The timestamp is the time since the export start.
+    fn write_frame(&mut self, frame: &Frame, timestamp: Duration) -> Result<(), Error>;
+    /// Complete the file (write indexes, update headers etc.)
+    fn finish(&mut self) -> Result<(), Error>;
+}
+
+/// Writes frames into both writers, e.g. pictures and a metadata sidecar
+impl<A: FrameWriter, B: FrameWriter> FrameWriter for (A, B) {
+    fn write_frame(&mut self, frame: &Frame, timestamp: Duration) -> Result<(), Error> {
+        self.0.write_frame(frame, timestamp)?;
+        self.1.write_frame(frame, timestamp)
+    }
+    fn finish(&mut self) -> Result<(), Error> {
+        let result = self.0.finish();
+        self.1.finish()?;
+        result
+    }
+}
+
+/// Export a recording (starting from its current position) and finish the writer. Returns the
+/// number of exported frames.
+pub fn export_recording(
+    reader: &mut RecordingReader,
+    writer: &mut impl FrameWriter,
+) -> Result<usize, Error> {
+    let mut frames = 0;
+    while let Some(recorded) = reader.read_frame()? {
+        writer.write_frame(&recorded.frame, recorded.timestamp)?;
+        frames += 1;
+    }
+    writer.finish()?;
+    Ok(frames)
+}
+
+/// Export frames of a live client (the stream must be selected) and finish the writer. The export
+/// is stopped when the number of frames or the duration (if set) is reached, or when the
+/// connection is closed (the writer is finished, the error is returned). Returns the number of
+/// exported frames.
+pub fn export_client(
+    client: &mut Client,
+    writer: &mut impl FrameWriter,
+    max_frames: Option<usize>,
+    max_duration: Option<Duration>,
+) -> Result<usize, Error> {
+    let mut frames = 0;
+    let mut started: Option<Instant> = None;
+    let mut result = Ok(());
+    while max_frames.map_or(true, |max| frames < max) {
+        let frame = match client.next() {
+            Some(Ok(frame)) => frame,
+            Some(Err(error)) => {
+                result = Err(error);
+                break;
+            }
+            None => break,
+        };
+        let timestamp = started.get_or_insert_with(Instant::now).elapsed();
+        if max_duration.map_or(false, |max| timestamp > max) {
+            break;
+        }
+        if let Err(error) = writer.write_frame(&frame, timestamp) {
+            result = Err(error);
+            break;
+        }
+        frames += 1;
+    }
+    writer.finish()?;
+    result.map(|()| frames)
+}
+
+/// Checks the raw frame data size
+fn check_raw_frame(format: Format, width: u16, height: u16, data: &[u8]) -> Result<(), Error> {
+    let bpp = format
+        .bytes_per_pixel()
+        .ok_or(Error::UnsupportedFormat(format))?;
+    if data.len() == usize::from(width) * usize::from(height) * bpp {
+        Ok(())
+    } else {
+        Err(Error::InvalidFrame)
+    }
+}
@@ -0,0 +1,58 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use super::FrameWriter;
+use crate::{Error, Frame};
+
+#[derive(Serialize)]
+struct SidecarRecord {
+    frame: usize,
+    timestamp: f64,
+    metadata: Option<serde_json::Value>,
+}
+
+/// Writes a JSON Lines file, which contains a record for each frame: the frame number, the
+/// timestamp (seconds since the export start) and the frame metadata, decoded from MessagePack
+/// (`null` if a frame has no metadata or it is not valid MessagePack). Usually combined with a
+/// picture writer as a tuple, e.g. `(ImageSequenceWriter, MetadataSidecar)`.
+pub struct MetadataSidecar {
+    file: BufWriter<File>,
+    frame_number: usize,
+}
+
+impl MetadataSidecar {
+    /// Create a new sidecar file
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self {
+            file: BufWriter::new(File::create(path)?),
+            frame_number: 0,
+        })
+    }
+}
+
+impl FrameWriter for MetadataSidecar {
+    fn write_frame(&mut self, frame: &Frame, timestamp: Duration) -> Result<(), Error> {
+        let record = SidecarRecord {
+            frame: self.frame_number,
+            timestamp: timestamp.as_secs_f64(),
+            metadata: frame
+                .metadata
+                .as_ref()
+                .and_then(|metadata| rmp_serde::from_slice(metadata).ok()),
+        };
+        serde_json::to_writer(&mut self.file, &record).map_err(std::io::Error::from)?;
+        self.file.write_all(b"\n")?;
+        self.frame_number += 1;
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
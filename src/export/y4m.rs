@@ -0,0 +1,82 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use super::{check_raw_frame, FrameWriter};
+use crate::{Error, Format, Frame, StreamInfo};
+
+/// Frame rates are written as `rate:RATE_SCALE`
+const RATE_SCALE: u32 = 1000;
+
+/// Writes frames of 8-bit luma streams into a YUV4MPEG2 (Y4M) file losslessly: luma frames are
+/// written as mono pictures, luma+alpha ones as `444alpha` pictures with neutral chroma. RGB
+/// formats are not supported, as the conversion into YCbCr is lossy (use
+/// [`super::ImageSequenceWriter`] instead). Y4M files have a constant frame rate, which must be
+/// specified in advance (frame timestamps are ignored).
+pub struct Y4mWriter {
+    file: BufWriter<File>,
+    format: Format,
+    width: u16,
+    height: u16,
+    planes: Vec<u8>,
+}
+
+impl Y4mWriter {
+    /// Create a new Y4M file for the stream
+    pub fn create(path: impl AsRef<Path>, info: &StreamInfo, fps: f64) -> Result<Self, Error> {
+        let colorspace = match info.format {
+            Format::Luma8 => "mono",
+            Format::LumaA8 => "444alpha",
+            format => return Err(Error::UnsupportedFormat(format)),
+        };
+        if !(fps.is_finite() && fps > 0.0) {
+            return Err(Error::InvalidFrameRate);
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(
+            file,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C{} XCOLORRANGE=FULL",
+            info.width,
+            info.height,
+            ((fps * f64::from(RATE_SCALE)).round() as u32).max(1),
+            RATE_SCALE,
+            colorspace
+        )?;
+        Ok(Self {
+            file,
+            format: info.format,
+            width: info.width,
+            height: info.height,
+            planes: Vec::new(),
+        })
+    }
+}
+
+impl FrameWriter for Y4mWriter {
+    fn write_frame(&mut self, frame: &Frame, _timestamp: Duration) -> Result<(), Error> {
+        let data = &frame.data[..];
+        check_raw_frame(self.format, self.width, self.height, data)?;
+        self.file.write_all(b"FRAME\n")?;
+        match self.format {
+            Format::Luma8 => self.file.write_all(data)?,
+            Format::LumaA8 => {
+                let pixels = data.len() / 2;
+                self.planes.clear();
+                self.planes.extend(data.chunks_exact(2).map(|p| p[0]));
+                // neutral chroma
+                self.planes.resize(pixels * 3, 128);
+                self.planes.extend(data.chunks_exact(2).map(|p| p[1]));
+                self.file.write_all(&self.planes)?;
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
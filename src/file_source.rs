@@ -0,0 +1,386 @@
+//! File-based streams: a local stream, fed by a background worker with images of a directory or
+//! frames of a recording, converted into the stream format (requires the `file-source` feature)
+use std::{
+    fs::{self, File},
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tracing::{trace, warn};
+
+use crate::{
+    source::{self, Next},
+    Error, Format, Frame, RecordingReader, Stream, StreamInfo,
+};
+
+const JPEG_QUALITY: u8 = 85;
+/// The worker is stopped if the number of failed frames in a row is reached (e.g. the whole
+/// source is unreadable)
+const MAX_FAILED_FRAMES: usize = 1000;
+
+/// A source of [`crate::Server::add_file_stream`]
+#[derive(Debug, Clone)]
+pub enum FileSource {
+    /// A directory of PNG/JPEG files, which are sent in the file name order
+    ImageFolder(PathBuf),
+    /// A recording file (the recorded timing is ignored)
+    Recording(PathBuf),
+}
+
+/// A decoded picture: 1-4 channels (luma, luma+alpha, RGB, RGBA) of 16-bit samples
+struct Picture {
+    width: usize,
+    height: usize,
+    channels: usize,
+    samples: Vec<u16>,
+}
+
+impl Picture {
+    fn from_u8(width: usize, height: usize, channels: usize, data: &[u8]) -> Self {
+        Self {
+            width,
+            height,
+            channels,
+            samples: data.iter().map(|v| u16::from(*v) * 257).collect(),
+        }
+    }
+    fn from_u16(width: usize, height: usize, channels: usize, samples: Vec<u16>) -> Self {
+        Self {
+            width,
+            height,
+            channels,
+            samples,
+        }
+    }
+    /// Gets (luma/red, green, blue, alpha) of a pixel
+    fn pixel(&self, x: usize, y: usize) -> [u16; 4] {
+        let pos = (y * self.width + x) * self.channels;
+        let p = &self.samples[pos..pos + self.channels];
+        match self.channels {
+            1 => [p[0], p[0], p[0], u16::MAX],
+            2 => [p[0], p[0], p[0], p[1]],
+            3 => [p[0], p[1], p[2], u16::MAX],
+            _ => [p[0], p[1], p[2], p[3]],
+        }
+    }
+    /// Converts the picture into the format, scaling it to the size (nearest neighbor)
+    fn convert(&self, format: Format, width: u16, height: u16) -> Result<Vec<u8>, Error> {
+        let (width, height) = (usize::from(width), usize::from(height));
+        if self.width == 0 || self.height == 0 {
+            return Err(Error::InvalidFrame);
+        }
+        let luma = |p: [u16; 4]| -> u16 {
+            if self.channels <= 2 {
+                p[0]
+            } else {
+                ((299 * u32::from(p[0]) + 587 * u32::from(p[1]) + 114 * u32::from(p[2])) / 1000)
+                    as u16
+            }
+        };
+        let mut data = Vec::with_capacity(width * height * 8);
+        for y in 0..height {
+            let sy = y * self.height / height;
+            for x in 0..width {
+                let p = self.pixel(x * self.width / width, sy);
+                // 16-bit samples are little-endian
+                match format {
+                    Format::Luma8 => data.push((luma(p) >> 8) as u8),
+                    Format::Luma16 => data.extend(luma(p).to_le_bytes()),
+                    Format::LumaA8 => data.extend([(luma(p) >> 8) as u8, (p[3] >> 8) as u8]),
+                    Format::LumaA16 => {
+                        data.extend(luma(p).to_le_bytes());
+                        data.extend(p[3].to_le_bytes());
+                    }
+                    Format::Rgb8 | Format::MJpeg => {
+                        data.extend(p[..3].iter().map(|v| (v >> 8) as u8))
+                    }
+                    Format::Rgb16 => data.extend(p[..3].iter().flat_map(|v| v.to_le_bytes())),
+                    Format::Rgba8 => data.extend(p.iter().map(|v| (v >> 8) as u8)),
+                    Format::Rgba16 => data.extend(p.iter().flat_map(|v| v.to_le_bytes())),
+                }
+            }
+        }
+        if format == Format::MJpeg {
+            let mut buf = Vec::new();
+            jpeg_encoder::Encoder::new(&mut buf, JPEG_QUALITY)
+                .encode(
+                    &data,
+                    width as u16,
+                    height as u16,
+                    jpeg_encoder::ColorType::Rgb,
+                )
+                .map_err(|_| Error::InvalidFrame)?;
+            return Ok(buf);
+        }
+        Ok(data)
+    }
+}
+
+fn decode_png(path: &Path) -> Result<Picture, Error> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    // palettes and low bit depths are expanded to 8-bit
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(png_error)?;
+    buf.truncate(info.buffer_size());
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+    Ok(if info.bit_depth == png::BitDepth::Sixteen {
+        // PNG samples are big-endian
+        let samples = buf
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        Picture::from_u16(width, height, channels, samples)
+    } else {
+        Picture::from_u8(width, height, channels, &buf)
+    })
+}
+
+fn decode_jpeg(data: &[u8]) -> Result<Picture, Error> {
+    use jpeg_decoder::PixelFormat;
+    let mut decoder = jpeg_decoder::Decoder::new(data);
+    let pixels = decoder.decode().map_err(|_| Error::InvalidFrame)?;
+    let info = decoder.info().ok_or(Error::InvalidFrame)?;
+    let (width, height) = (usize::from(info.width), usize::from(info.height));
+    Ok(match info.pixel_format {
+        PixelFormat::L8 => Picture::from_u8(width, height, 1, &pixels),
+        PixelFormat::L16 => {
+            let samples = pixels
+                .chunks_exact(2)
+                .map(|s| u16::from_ne_bytes([s[0], s[1]]))
+                .collect();
+            Picture::from_u16(width, height, 1, samples)
+        }
+        PixelFormat::RGB24 => Picture::from_u8(width, height, 3, &pixels),
+        PixelFormat::CMYK32 => {
+            // Adobe CMYK JPEGs are stored inverted
+            let rgb: Vec<u8> = pixels
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let k = u16::from(p[3]);
+                    [0, 1, 2].map(|i| (u16::from(p[i]) * k / 255) as u8)
+                })
+                .collect();
+            Picture::from_u8(width, height, 3, &rgb)
+        }
+    })
+}
+
+/// Decodes a raw or MJPEG frame of a recording
+fn decode_frame(info: &StreamInfo, data: &[u8]) -> Result<Picture, Error> {
+    let (width, height) = (usize::from(info.width), usize::from(info.height));
+    let (channels, wide) = match info.format {
+        Format::Luma8 => (1, false),
+        Format::Luma16 => (1, true),
+        Format::LumaA8 => (2, false),
+        Format::LumaA16 => (2, true),
+        Format::Rgb8 => (3, false),
+        Format::Rgb16 => (3, true),
+        Format::Rgba8 => (4, false),
+        Format::Rgba16 => (4, true),
+        Format::MJpeg => return decode_jpeg(data),
+    };
+    let size = width * height * channels * if wide { 2 } else { 1 };
+    if data.len() != size {
+        return Err(Error::InvalidFrame);
+    }
+    Ok(if wide {
+        let samples = data
+            .chunks_exact(2)
+            .map(|s| u16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        Picture::from_u16(width, height, channels, samples)
+    } else {
+        Picture::from_u8(width, height, channels, data)
+    })
+}
+
+fn png_error(error: png::DecodingError) -> Error {
+    match error {
+        png::DecodingError::IoError(error) => error.into(),
+        _ => Error::InvalidFrame,
+    }
+}
+
+fn is_jpeg(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| {
+            ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg")
+        })
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// Lists PNG/JPEG files of the directory, sorted by names
+fn list_images(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && (is_png(&path) || is_jpeg(&path)) {
+            files.push(path);
+        }
+    }
+    if files.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no PNG/JPEG files found").into());
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub(crate) enum Reader {
+    Images { files: Vec<PathBuf>, pos: usize },
+    Recording(RecordingReader),
+}
+
+impl Reader {
+    /// The recording stream name
+    pub(crate) fn name(&self) -> Option<&str> {
+        match self {
+            Reader::Images { .. } => None,
+            Reader::Recording(reader) => reader.name(),
+        }
+    }
+    /// Reads the next frame, converted into the stream format. Returns `None` at the end of the
+    /// source.
+    fn next(&mut self, info: &StreamInfo) -> Option<Result<Frame, Error>> {
+        match self {
+            Reader::Images { files, pos } => {
+                let path = files.get(*pos)?;
+                *pos += 1;
+                Some(load_image(path, info).map_err(|error| {
+                    trace!(path = %path.display(), %error, "unable to load image");
+                    error
+                }))
+            }
+            Reader::Recording(reader) => match reader.read_frame() {
+                Ok(Some(recorded)) => {
+                    let recorded_info = reader.stream_info();
+                    // frames of the same format and size are sent as-is, with metadata
+                    if recorded_info.format == info.format
+                        && recorded_info.width == info.width
+                        && recorded_info.height == info.height
+                    {
+                        return Some(Ok(recorded.frame));
+                    }
+                    Some(
+                        decode_frame(recorded_info, &recorded.frame.data)
+                            .and_then(|picture| {
+                                picture.convert(info.format, info.width, info.height)
+                            })
+                            .map(|data| Frame {
+                                metadata: recorded.frame.metadata,
+                                data: data.into(),
+                            }),
+                    )
+                }
+                Ok(None) => None,
+                Err(error) => Some(Err(error)),
+            },
+        }
+    }
+    fn rewind(&mut self) -> Result<(), Error> {
+        match self {
+            Reader::Images { pos, .. } => *pos = 0,
+            Reader::Recording(reader) => reader.seek_frame(0)?,
+        }
+        Ok(())
+    }
+}
+
+fn load_image(path: &Path, info: &StreamInfo) -> Result<Frame, Error> {
+    if is_jpeg(path) {
+        let data = fs::read(path)?;
+        let picture = decode_jpeg(&data)?;
+        // JPEG files of the stream size are sent to MJPEG streams as-is
+        if info.format == Format::MJpeg
+            && picture.width == usize::from(info.width)
+            && picture.height == usize::from(info.height)
+        {
+            return Ok(data.into());
+        }
+        Ok(picture
+            .convert(info.format, info.width, info.height)?
+            .into())
+    } else {
+        Ok(decode_png(path)?
+            .convert(info.format, info.width, info.height)?
+            .into())
+    }
+}
+
+/// Opens the source
+pub(crate) fn open(source: &FileSource) -> Result<Reader, Error> {
+    Ok(match source {
+        FileSource::ImageFolder(dir) => Reader::Images {
+            files: list_images(dir)?,
+            pos: 0,
+        },
+        FileSource::Recording(path) => {
+            let reader = RecordingReader::open(path)?;
+            if reader.frame_count() == 0 {
+                return Err(Error::InvalidRecording);
+            }
+            Reader::Recording(reader)
+        }
+    })
+}
+
+/// Starts a background worker, which feeds the stream from the source
+pub(crate) fn spawn(
+    mut reader: Reader,
+    stream: Stream,
+    info: StreamInfo,
+    interval: Duration,
+    looped: bool,
+) {
+    let stream_id = stream.id;
+    let mut failed = 0;
+    // failures of the current pass, logged once the pass is finished
+    let mut pass_failed = 0;
+    let mut last_error = None;
+    source::spawn(stream, interval, move || loop {
+        match reader.next(&info) {
+            Some(Ok(frame)) => {
+                failed = 0;
+                return Next::Frame(frame);
+            }
+            Some(Err(error)) => {
+                failed += 1;
+                if failed >= MAX_FAILED_FRAMES {
+                    warn!(stream_id, %error, "file source failed");
+                    return Next::Stop;
+                }
+                pass_failed += 1;
+                last_error = Some(error);
+                return Next::Skip;
+            }
+            None => {
+                if let Some(error) = last_error.take() {
+                    warn!(
+                        stream_id,
+                        failed = pass_failed,
+                        %error,
+                        "file source frames skipped"
+                    );
+                    pass_failed = 0;
+                }
+                if !looped {
+                    trace!(stream_id, "file source finished");
+                    return Next::Stop;
+                }
+                if let Err(error) = reader.rewind() {
+                    warn!(stream_id, %error, "file source failed");
+                    return Next::Stop;
+                }
+            }
+        }
+    });
+}
@@ -1,22 +1,67 @@
 #![ doc = include_str!( concat!( env!( "CARGO_MANIFEST_DIR" ), "/", "README.md" ) ) ]
 #![deny(missing_docs)]
 use core::fmt;
-use std::{sync::Arc, time::Duration};
+use std::{
+    io::{Read, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use binrw::binrw;
+use bytemuck::Pod;
+pub use bytes::Bytes;
 
+mod access;
+mod auth;
 mod client;
 #[cfg(feature = "async")]
 mod client_async;
+mod clip;
+mod continuous;
+#[cfg(feature = "export")]
+pub mod export;
+#[cfg(feature = "file-source")]
+mod file_source;
+mod multicast;
+mod params;
+mod pool;
+mod publish;
+mod recording;
+mod remote;
+mod replay;
 mod server;
+#[cfg(all(feature = "shm", target_os = "linux"))]
+mod shm;
+#[cfg(any(feature = "test-pattern", feature = "file-source"))]
+mod source;
+#[cfg(feature = "test-pattern")]
+mod test_pattern;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub use access::AccessPolicy;
 pub use client::Client;
 #[cfg(feature = "async")]
 pub use client_async::ClientAsync;
+use clip::ClipBuffer;
+pub use continuous::{ContinuousRecorder, ContinuousRecording};
+#[cfg(feature = "file-source")]
+pub use file_source::FileSource;
 use once_cell::sync::Lazy;
+pub use params::{Param, ParamHandler, ParamInfo, ParamKind, ParamValue};
+pub use pool::{FrameBuffer, FramePool};
+use publish::Publisher;
+pub use recording::{RecordedFrame, Recorder, RecordingReader};
+pub use replay::{Replay, ReplaySpeed};
 use serde::{Deserialize, Serialize};
 pub use server::Server;
 use server::StreamServerInner;
-use std::net::ToSocketAddrs;
+#[cfg(all(feature = "shm", target_os = "linux"))]
+pub use shm::ShmFrame;
+use std::net::{SocketAddr, ToSocketAddrs};
+#[cfg(feature = "test-pattern")]
+pub use test_pattern::{TestPattern, TestPicture};
 
 #[cfg(feature = "locking-default")]
 use parking_lot::{Condvar, Mutex, RawMutex};
@@ -29,6 +74,121 @@ use rtsc::pi::{Condvar, Mutex, RawMutex};
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Stream id, selected by clients to open a control session
+const CONTROL_STREAM_ID: u16 = u16::MAX;
+
+const SELECT_STATUS_OK: u8 = 0;
+const SELECT_STATUS_INVALID_STREAM: u8 = 1;
+const SELECT_STATUS_ACCESS_DENIED: u8 = 2;
+const SELECT_STATUS_UNSUPPORTED: u8 = 3;
+
+/// Stream select flag: frames are sent over shared memory
+#[cfg(all(feature = "shm", target_os = "linux"))]
+const STREAM_FLAG_SHM: u8 = 0x01;
+/// Stream select flag: frames are received from the stream multicast group
+const STREAM_FLAG_MULTICAST: u8 = 0x02;
+
+fn check_select_status(status: u8) -> Result<(), Error> {
+    match status {
+        SELECT_STATUS_OK => Ok(()),
+        SELECT_STATUS_INVALID_STREAM => Err(Error::InvalidStream),
+        SELECT_STATUS_ACCESS_DENIED => Err(Error::AccessDenied),
+        SELECT_STATUS_UNSUPPORTED => Err(Error::UnsupportedTransport),
+        _ => Err(Error::NotReady),
+    }
+}
+
+/// Sent by the server instead of GREETINGS if the client is rejected
+const SERVER_ERROR_MAGIC: u8 = b'E';
+
+/// Checks if the server has sent an error instead of GREETINGS
+fn check_server_error(greetings_buf: &[u8; 4]) -> Result<(), Error> {
+    if greetings_buf[0] == SERVER_ERROR_MAGIC {
+        check_select_status(greetings_buf[1])?;
+        return Err(Error::NotReady);
+    }
+    Ok(())
+}
+
+/// Max control request size (a 255-byte parameter name and a value fit with a large margin)
+const MAX_CONTROL_REQUEST_LEN: usize = 4096;
+
+/// Max control response size (fits lists of the max number of streams or parameters with
+/// 255-byte names)
+const MAX_CONTROL_RESPONSE_LEN: usize = 32 * 1024 * 1024;
+
+const CLIENT_MSG_ACK: u8 = 0x00;
+const CLIENT_MSG_EVENT: u8 = 0x01;
+
+/// Stream id, selected by publishers to push a stream to the server
+const PUBLISH_STREAM_ID: u16 = u16::MAX - 1;
+
+const PUBLISH_MSG_KEEPALIVE: u8 = 0x00;
+const PUBLISH_MSG_FRAME: u8 = 0x01;
+
+/// Writes the frame metadata and picture data blocks
+fn write_frame_blocks(socket: &mut impl Write, frame: &Frame) -> Result<(), Error> {
+    let metadata_len = u32::try_from(frame.metadata.as_ref().map_or(0, |v| v.len()))
+        .map_err(|_| Error::FrameMetaDataTooLarge)?;
+    socket.write_all(&metadata_len.to_le_bytes())?;
+    if let Some(ref metadata) = frame.metadata {
+        socket.write_all(metadata)?;
+    }
+    let data_len = u32::try_from(frame.data.len()).map_err(|_| Error::FrameDataTooLarge)?;
+    socket.write_all(&data_len.to_le_bytes())?;
+    socket.write_all(&frame.data)?;
+    Ok(())
+}
+
+/// Reads the frame metadata and picture data blocks
+fn read_frame_blocks(socket: &mut impl Read) -> Result<Frame, Error> {
+    read_frame_blocks_limited(socket, usize::MAX, usize::MAX)
+}
+
+/// Reads the frame metadata and picture data blocks, larger blocks are rejected before being
+/// allocated
+fn read_frame_blocks_limited(
+    socket: &mut impl Read,
+    max_metadata_len: usize,
+    max_data_len: usize,
+) -> Result<Frame, Error> {
+    let mut len_buf = [0u8; 4];
+    socket.read_exact(&mut len_buf)?;
+    let len =
+        usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::FrameMetaDataTooLarge)?;
+    if len > max_metadata_len {
+        return Err(Error::FrameMetaDataTooLarge);
+    }
+    let metadata = if len > 0 {
+        let mut buf = vec![0u8; len];
+        socket.read_exact(&mut buf)?;
+        Some(buf.into())
+    } else {
+        None
+    };
+    socket.read_exact(&mut len_buf)?;
+    let len = usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::FrameDataTooLarge)?;
+    if len > max_data_len {
+        return Err(Error::FrameDataTooLarge);
+    }
+    let mut data = vec![0u8; len];
+    socket.read_exact(&mut data)?;
+    Ok(Frame {
+        metadata,
+        data: data.into(),
+    })
+}
+
+/// Frame interval of background workers, which send frames at the given rate
+#[cfg(any(feature = "test-pattern", feature = "file-source"))]
+fn frame_interval(fps: f64) -> Result<Duration, Error> {
+    if fps.is_finite() && fps > 0.0 {
+        Ok(Duration::from_secs_f64(1.0 / fps))
+    } else {
+        Err(Error::InvalidFrameRate)
+    }
+}
+
 static DEFAULT_SERVER: Lazy<Server> = Lazy::new(|| Server::new(DEFAULT_TIMEOUT));
 
 /// Add a stream to the default server
@@ -36,6 +196,11 @@ pub fn add_stream(format: Format, width: u16, height: u16) -> Result<Stream, Err
     DEFAULT_SERVER.add_stream(format, width, height)
 }
 
+/// Add a parameter to the default server
+pub fn add_param(name: &str, kind: ParamKind, value: ParamValue) -> Result<Param, Error> {
+    DEFAULT_SERVER.add_param(name, kind, value)
+}
+
 /// Send frame to the default server with stream id
 pub fn send_frame(stream_id: u16, frame: Frame) -> Result<(), Error> {
     DEFAULT_SERVER.send_frame(stream_id, frame)
@@ -51,8 +216,9 @@ pub fn serve(addr: impl ToSocketAddrs + std::fmt::Debug) -> Result<(), Error> {
 pub struct Frame {
     /// An optional metadata (encoded in a way, known to remotes)
     pub metadata: Option<Arc<Vec<u8>>>,
-    /// The frame data (encoded/compressed into the stream format)
-    pub data: Arc<Vec<u8>>,
+    /// The frame data (encoded/compressed into the stream format). Cloning is cheap, the data is
+    /// never copied by the server.
+    pub data: Bytes,
 }
 
 impl From<Vec<u8>> for Frame {
@@ -66,6 +232,12 @@ impl From<Vec<u8>> for Frame {
 
 impl From<Arc<Vec<u8>>> for Frame {
     fn from(data: Arc<Vec<u8>>) -> Self {
+        Self::new(data)
+    }
+}
+
+impl From<Bytes> for Frame {
+    fn from(data: Bytes) -> Self {
         Self {
             metadata: None,
             data,
@@ -73,13 +245,31 @@ impl From<Arc<Vec<u8>>> for Frame {
     }
 }
 
+/// Makes shared vectors owners of frame data
+struct ArcOwner(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for ArcOwner {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Makes typed pixel buffers owners of frame data
+struct PixelOwner<P, T>(P, PhantomData<fn() -> T>);
+
+impl<P: AsRef<[T]>, T: Pod> AsRef<[u8]> for PixelOwner<P, T> {
+    fn as_ref(&self) -> &[u8] {
+        bytemuck::cast_slice(self.0.as_ref())
+    }
+}
+
 impl Frame {
     /// Create a new frame with no metadata. Arc is used to avoid copying the data, as many video
     /// apps already cover their data with Arc.
     pub fn new(data: Arc<Vec<u8>>) -> Self {
         Self {
             metadata: None,
-            data,
+            data: Bytes::from_owner(ArcOwner(data)),
         }
     }
     /// Create a new frame with metadata. Arc is used to avoid copying the data, as many video apps
@@ -88,13 +278,42 @@ impl Frame {
     pub fn new_with_metadata(metadata: Arc<Vec<u8>>, data: Arc<Vec<u8>>) -> Self {
         Self {
             metadata: Some(metadata),
-            data,
+            ..Self::new(data)
+        }
+    }
+    /// Create a new frame, which data is kept in an external buffer (e.g. a camera SDK buffer or
+    /// a memory-mapped region) without copying. The buffer is released when the frame is dropped
+    /// by the server and all clients.
+    pub fn from_owner(owner: impl AsRef<[u8]> + Send + 'static) -> Self {
+        Self {
+            metadata: None,
+            data: Bytes::from_owner(owner),
         }
     }
+    /// Create a new frame from typed pixel data (e.g. `Vec<u16>` for [`Format::Luma16`]) without
+    /// copying. Samples are sent in the native byte order, while 16-bit formats are
+    /// little-endian, so on big-endian platforms samples must be swapped in advance.
+    pub fn from_pixels<T: Pod>(pixels: impl AsRef<[T]> + Send + 'static) -> Self {
+        Self::from_owner(PixelOwner(pixels, PhantomData))
+    }
+    /// Set the frame metadata
+    pub fn with_metadata(mut self, metadata: Arc<Vec<u8>>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+    /// Get the frame data as typed pixel data (e.g. `&[u16]` for [`Format::Luma16`], in the native
+    /// byte order) without copying. Returns `None` if the data is not aligned for the type or its
+    /// size is not a multiple of the type size.
+    pub fn pixels<T: Pod>(&self) -> Option<&[T]> {
+        bytemuck::try_cast_slice(&self.data).ok()
+    }
 }
 
 /// Server API version
-pub const API_VERSION: u8 = 2;
+pub const API_VERSION: u8 = 5;
+
+/// Max client event size
+pub const MAX_EVENT_SIZE: usize = 65_536;
 
 /// Error type
 #[derive(thiserror::Error, Debug)]
@@ -102,7 +321,7 @@ pub enum Error {
     /// Invalid stream (not known to the server)
     #[error("Invalid stream")]
     InvalidStream,
-    /// Too many streams (max supported per server is u16::MAX)
+    /// Too many streams (max supported per server is 65534, ids 65534 and 65535 are reserved)
     #[error("Too many streams")]
     TooManyStreams,
     /// IO error
@@ -120,6 +339,50 @@ pub enum Error {
     /// Frame data is larger than u32::MAX
     #[error("Frame data too large")]
     FrameDataTooLarge,
+    /// Client event is larger than [`MAX_EVENT_SIZE`]
+    #[error("Event too large")]
+    EventTooLarge,
+    /// Parameter not found
+    #[error("Parameter not found")]
+    ParamNotFound,
+    /// Invalid parameter (bad or duplicate name, too many parameters, invalid protocol status)
+    #[error("Invalid parameter")]
+    InvalidParam,
+    /// Parameter value does not match the parameter type or range
+    #[error("Invalid parameter value")]
+    InvalidParamValue,
+    /// Access denied (authentication failed)
+    #[error("Access denied")]
+    AccessDenied,
+    /// Authentication method, requested by the server, is not supported
+    #[error("Unsupported authentication method: {0}")]
+    UnsupportedAuth(u8),
+    /// Transport mode, requested by the client, is not supported by the server or the connection
+    #[error("Unsupported transport")]
+    UnsupportedTransport,
+    /// Stream name is longer than 255 bytes
+    #[error("Invalid stream name")]
+    InvalidStreamName,
+    /// Invalid or corrupted recording file, or non-monotonic frame timestamps
+    #[error("Invalid recording")]
+    InvalidRecording,
+    /// Replay speed factor is not a positive number
+    #[error("Invalid replay speed")]
+    InvalidReplaySpeed,
+    /// Frame rate is not a positive number
+    #[error("Invalid frame rate")]
+    InvalidFrameRate,
+    /// Clip capture is not enabled for the stream or the clip is longer than the buffer
+    #[error("Invalid clip")]
+    InvalidClip,
+    /// The stream format is not supported by the exporter
+    #[error(
+        "Unsupported format: {0:?} (use PNG/TIFF image sequences to export raw formats losslessly)"
+    )]
+    UnsupportedFormat(Format),
+    /// Frame data does not match the stream format or picture size
+    #[error("Invalid frame")]
+    InvalidFrame,
     /// Invalid TCP/IP address/host name/port
     #[error("Invalid address")]
     InvalidAddress,
@@ -130,6 +393,10 @@ pub enum Error {
     #[error("Timed out")]
     #[cfg(feature = "async")]
     AsyncTimeout(#[from] tokio::time::error::Elapsed),
+    /// TLS errors
+    #[error("TLS error: {0}")]
+    #[cfg(feature = "tls")]
+    Tls(#[from] rustls::Error),
 }
 
 /// Video formats. Note: a frame MUST be MANUALLY encoded/compressed with the selected format
@@ -159,6 +426,21 @@ pub enum Format {
     MJpeg = 64,
 }
 
+impl Format {
+    /// Bytes per pixel of raw formats (`None` for MJPEG)
+    pub fn bytes_per_pixel(self) -> Option<usize> {
+        match self {
+            Format::Luma8 => Some(1),
+            Format::Luma16 | Format::LumaA8 => Some(2),
+            Format::LumaA16 | Format::Rgba8 => Some(4),
+            Format::Rgb8 => Some(3),
+            Format::Rgb16 => Some(6),
+            Format::Rgba16 => Some(8),
+            Format::MJpeg => None,
+        }
+    }
+}
+
 /// The default bounding box which can be used in custom applications. The bounding box format is
 /// also recognized by [rvideo-view](https://crates.io/crates/rvideo-view).
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -178,6 +460,33 @@ pub struct BoundingBox {
     pub height: u16,
 }
 
+/// An application-defined message, sent by a client to a stream
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// Stream id
+    pub stream_id: u16,
+    /// Client id (unique per server)
+    pub client_id: usize,
+    /// Event data (encoded in a way, known to the application)
+    pub data: Vec<u8>,
+}
+
+/// Stream event handler
+pub type EventHandler = Arc<dyn Fn(Event) + Send + Sync>;
+
+/// Mouse click on a picture. Sent by [rvideo-view](https://crates.io/crates/rvideo-view) as a
+/// client event, encoded in MessagePack as a map with a single `.click` field.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Click {
+    /// The x coordinate in picture pixels
+    pub x: u16,
+    /// The y coordinate in picture pixels
+    pub y: u16,
+    /// Mouse button (0 - primary, 1 - secondary, 2 - middle)
+    #[serde(rename = "b")]
+    pub button: u8,
+}
+
 #[binrw]
 #[brw(little, magic = b"R")]
 #[derive(Clone, Debug)]
@@ -192,6 +501,16 @@ struct Greetings {
 struct StreamSelect {
     stream_id: u16,
     max_fps: u8,
+    flags: u8,
+}
+
+/// Stream description, listed by clients in control sessions
+#[derive(Clone, Debug)]
+pub struct StreamDescription {
+    /// Stream information
+    pub info: StreamInfo,
+    /// Stream name (if set)
+    pub name: Option<String>,
 }
 
 /// Stream information
@@ -219,6 +538,28 @@ impl fmt::Display for StreamInfo {
     }
 }
 
+/// A summary of stream clients (see [`Stream::client_summary`])
+#[derive(Debug, Clone, Default)]
+pub struct ClientSummary {
+    /// Max FPS, requested by each client. `None` for local clients (recorders, publishers,
+    /// multicast senders), which receive all frames.
+    pub max_fps: Vec<Option<u8>>,
+}
+
+impl ClientSummary {
+    /// The number of clients
+    pub fn clients(&self) -> usize {
+        self.max_fps.len()
+    }
+    /// The frame rate, enough to satisfy all clients: `None` if all frames are required (there
+    /// are local clients), zero if there are no clients
+    pub fn required_fps(&self) -> Option<u8> {
+        self.max_fps
+            .iter()
+            .try_fold(0, |required, fps| fps.map(|fps| required.max(fps)))
+    }
+}
+
 /// A stream helper object. Contains a stream id and a reference to the server inner object
 #[derive(Clone)]
 pub struct Stream {
@@ -231,8 +572,118 @@ impl Stream {
     pub fn id(&self) -> u16 {
         self.id
     }
+    /// Set the stream name (max 255 bytes), which is listed to clients
+    pub fn set_name(&self, name: &str) -> Result<(), Error> {
+        self.server_inner.set_stream_name(self.id, name)
+    }
+    /// Get the stream name
+    pub fn name(&self) -> Option<String> {
+        self.server_inner.stream_name(self.id)
+    }
+    /// Check if the stream is online. Local streams are always online, remote ones (see
+    /// [`Server::add_remote_stream`]) are offline while the remote server is unavailable.
+    pub fn is_online(&self) -> bool {
+        self.server_inner.stream_online(self.id)
+    }
     /// Send a frame to the stream
     pub fn send_frame(&self, frame: Frame) -> Result<(), Error> {
         self.server_inner.send_frame(self.id, frame)
     }
+    /// Create a pool of frame buffers for the stream (see [`FramePool`]), with the given number
+    /// of buffers pre-allocated. Buffers of raw streams have the frame size (width * height *
+    /// bytes per pixel). Buffers of MJPEG streams are empty, with the capacity of an RGB8 picture
+    /// (width * height * 3) reserved, which fits JPEG pictures of usual quality (buffers grow if
+    /// larger pictures are written). Use [`FramePool::with_capacity`] to reserve a custom
+    /// capacity.
+    pub fn frame_pool(&self, buffers: usize) -> Result<FramePool, Error> {
+        let info = self.server_inner.stream_info(self.id)?;
+        let pixels = usize::from(info.width) * usize::from(info.height);
+        Ok(match info.format.bytes_per_pixel() {
+            Some(bpp) => FramePool::create(pixels * bpp, true, buffers),
+            None => FramePool::create(pixels * 3, false, buffers),
+        })
+    }
+    /// Check if the stream has clients (including local ones: recorders, publishers, multicast
+    /// senders). If there are no clients, sent frames are dropped (but still kept in the clip
+    /// buffer, if enabled), so producers can skip expensive encoding.
+    pub fn has_clients(&self) -> bool {
+        self.server_inner.has_clients(self.id)
+    }
+    /// Block until the stream has clients or the timeout is reached. Returns true if there are
+    /// clients.
+    pub fn wait_for_clients(&self, timeout: Duration) -> bool {
+        self.server_inner.wait_for_clients(self.id, timeout)
+    }
+    /// Get the summary of stream clients, e.g. to throttle the frame production to the rate,
+    /// requested by clients
+    pub fn client_summary(&self) -> ClientSummary {
+        self.server_inner.client_summary(self.id)
+    }
+    /// Require clients of the stream to authenticate with the given pre-shared secret (overrides
+    /// the server secret)
+    pub fn set_secret(&self, secret: &[u8]) {
+        self.server_inner.set_stream_secret(self.id, secret);
+    }
+    /// Deliver frames of the stream to a UDP multicast group as well. Clients, which request
+    /// multicast delivery, receive the group address during the handshake, so each frame is sent
+    /// once for all of them (FPS limits of such clients are ignored).
+    pub fn set_multicast(&self, group: SocketAddr) -> Result<(), Error> {
+        self.server_inner.set_stream_multicast(self.id, group)
+    }
+    /// Publish the stream to a hub server (see [`Server::allow_publishing`]), e.g. if the device
+    /// is behind NAT and can not be reached by clients. The stream must have a name, which
+    /// identifies it on the hub. The stream is pushed by a background worker, which reconnects
+    /// automatically.
+    pub fn publish(&self, hub: impl ToSocketAddrs, secret: Option<&[u8]>) -> Result<(), Error> {
+        Publisher {
+            addr: hub.to_socket_addrs()?.next().ok_or(Error::InvalidAddress)?,
+            secret: secret.map(<[u8]>::to_vec),
+            timeout: self.server_inner.timeout,
+        }
+        .spawn(self.clone())
+    }
+    /// Record the stream continuously into segmented files (see [`ContinuousRecording`]). The
+    /// recording is written by a background worker, which receives frames as a local client, so
+    /// [`Stream::send_frame`] is never blocked by disk writes (frames are dropped if the disk is
+    /// slower than the stream).
+    pub fn record(&self, config: ContinuousRecording) -> Result<ContinuousRecorder, Error> {
+        ContinuousRecorder::spawn(config, self.clone())
+    }
+    /// Enable clip capture (see [`Stream::trigger_clip`]): keep frames, sent during the given
+    /// period, but no more than `max_frames`, in a ring buffer. Clips are written into the
+    /// directory (created if missing).
+    pub fn enable_clips(
+        &self,
+        dir: impl AsRef<Path>,
+        duration: Duration,
+        max_frames: usize,
+    ) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        self.server_inner
+            .set_stream_clip_buffer(self.id, ClipBuffer::new(dir, duration, max_frames));
+        Ok(())
+    }
+    /// Save a clip: buffered frames, sent within `pre` before the trigger, and frames, sent within
+    /// `post` after it, are written into a recording file (in the recording, the trigger moment
+    /// is at the `pre` timestamp). Both periods must fit the clip buffer duration. The file is
+    /// named after the label and is written by a background worker when the post-trigger period
+    /// ends, the method returns its path immediately.
+    pub fn trigger_clip(
+        &self,
+        pre: Duration,
+        post: Duration,
+        label: &str,
+    ) -> Result<PathBuf, Error> {
+        let clip = self.server_inner.start_clip(self.id, pre, post, label)?;
+        let path = clip.path.clone();
+        clip.spawn(self.clone());
+        Ok(path)
+    }
+    /// Set a handler for events, sent by clients of the stream. The handler is called in client
+    /// connection threads so it should not block.
+    pub fn on_event(&self, handler: impl Fn(Event) + Send + Sync + 'static) {
+        self.server_inner
+            .set_event_handler(self.id, Arc::new(handler));
+    }
 }
@@ -0,0 +1,363 @@
+//! UDP multicast frame delivery. Frames are split into datagrams, each one carries a header with
+//! the stream id, the frame sequence number and the fragment position. Clients reassemble frames
+//! and drop incomplete ones.
+use std::{
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::trace;
+
+use crate::{Error, Frame};
+
+/// Max fragment payload size, fits the common Ethernet MTU
+const CHUNK_SIZE: usize = 1400;
+
+const HEADER_SIZE: usize = 10;
+
+/// Max datagram size
+pub(crate) const DATAGRAM_SIZE: usize = HEADER_SIZE + CHUNK_SIZE;
+
+const RECV_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+
+const FAMILY_V4: u8 = 4;
+const FAMILY_V6: u8 = 6;
+
+/// Creates a socket for sending frames to the group
+pub(crate) fn sender(group: SocketAddr) -> Result<UdpSocket, Error> {
+    if !group.ip().is_multicast() {
+        return Err(Error::InvalidAddress);
+    }
+    let bind_ip: IpAddr = if group.is_ipv4() {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv6Addr::UNSPECIFIED.into()
+    };
+    Ok(UdpSocket::bind((bind_ip, 0))?)
+}
+
+/// Creates a socket, joined to the group. The address is reused, so multiple clients on the same
+/// host can receive frames of the same group.
+pub(crate) fn receiver(group: SocketAddr, timeout: Duration) -> Result<UdpSocket, Error> {
+    let socket = Socket::new(Domain::for_address(group), Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    // a smaller buffer is not critical, more frames are dropped only
+    let _r = socket.set_recv_buffer_size(RECV_BUFFER_SIZE);
+    match group.ip() {
+        IpAddr::V4(ip) => {
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, group.port())).into())?;
+            socket.join_multicast_v4(&ip, &Ipv4Addr::UNSPECIFIED)?;
+        }
+        IpAddr::V6(ip) => {
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, group.port())).into())?;
+            socket.join_multicast_v6(&ip, 0)?;
+        }
+    }
+    let socket: UdpSocket = socket.into();
+    socket.set_read_timeout(Some(timeout))?;
+    Ok(socket)
+}
+
+/// Sends a frame to the group as a sequence of datagrams
+pub(crate) fn send_frame(
+    socket: &UdpSocket,
+    group: SocketAddr,
+    stream_id: u16,
+    seq: u32,
+    frame: &Frame,
+) -> Result<(), Error> {
+    pack_datagrams(stream_id, seq, frame, |datagram| {
+        socket.send_to(datagram, group)?;
+        Ok(())
+    })
+}
+
+/// Splits a frame into datagrams, which are passed to the sender one by one
+fn pack_datagrams(
+    stream_id: u16,
+    seq: u32,
+    frame: &Frame,
+    mut send: impl FnMut(&[u8]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let metadata = frame.metadata.as_ref().map_or(&[][..], |v| v.as_slice());
+    let mut payload = Vec::with_capacity(metadata.len() + frame.data.len() + 8);
+    payload.extend(
+        u32::try_from(metadata.len())
+            .map_err(|_| Error::FrameMetaDataTooLarge)?
+            .to_le_bytes(),
+    );
+    payload.extend(metadata);
+    payload.extend(
+        u32::try_from(frame.data.len())
+            .map_err(|_| Error::FrameDataTooLarge)?
+            .to_le_bytes(),
+    );
+    payload.extend(frame.data.iter());
+    let count =
+        u16::try_from(payload.len().div_ceil(CHUNK_SIZE)).map_err(|_| Error::FrameDataTooLarge)?;
+    let mut datagram = Vec::with_capacity(DATAGRAM_SIZE);
+    for (index, chunk) in payload.chunks(CHUNK_SIZE).enumerate() {
+        datagram.clear();
+        datagram.extend(stream_id.to_le_bytes());
+        datagram.extend(seq.to_le_bytes());
+        datagram.extend(u16::try_from(index).unwrap().to_le_bytes());
+        datagram.extend(count.to_le_bytes());
+        datagram.extend(chunk);
+        send(&datagram)?;
+    }
+    Ok(())
+}
+
+/// Packs the group address for MULTICAST-INFO
+pub(crate) fn pack_group(group: SocketAddr) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(19);
+    match group.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(FAMILY_V4);
+            buf.extend(ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(FAMILY_V6);
+            buf.extend(ip.octets());
+        }
+    }
+    buf.extend(group.port().to_le_bytes());
+    buf
+}
+
+/// The length of the MULTICAST-INFO address and port
+pub(crate) fn group_len(family: u8) -> Result<usize, Error> {
+    match family {
+        FAMILY_V4 => Ok(6),
+        FAMILY_V6 => Ok(18),
+        _ => Err(Error::InvalidAddress),
+    }
+}
+
+/// Unpacks MULTICAST-INFO from the family byte and the rest of the message
+pub(crate) fn unpack_group(family: u8, stream: &mut impl Read) -> Result<SocketAddr, Error> {
+    let ip: IpAddr = match family {
+        FAMILY_V4 => {
+            let mut octets = [0u8; 4];
+            stream.read_exact(&mut octets)?;
+            Ipv4Addr::from(octets).into()
+        }
+        FAMILY_V6 => {
+            let mut octets = [0u8; 16];
+            stream.read_exact(&mut octets)?;
+            Ipv6Addr::from(octets).into()
+        }
+        _ => return Err(Error::InvalidAddress),
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port)?;
+    Ok(SocketAddr::new(ip, u16::from_le_bytes(port)))
+}
+
+/// Reassembles frames from datagrams. A frame is dropped if a datagram of a newer one is received
+/// before the frame is complete.
+pub(crate) struct Reassembler {
+    stream_id: u16,
+    seq: Option<u32>,
+    chunks: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+impl Reassembler {
+    pub(crate) fn new(stream_id: u16) -> Self {
+        Self {
+            stream_id,
+            seq: None,
+            chunks: Vec::new(),
+            received: 0,
+        }
+    }
+    /// Processes a datagram, returns a frame if it is complete
+    pub(crate) fn push(&mut self, datagram: &[u8]) -> Result<Option<Frame>, Error> {
+        if datagram.len() < HEADER_SIZE {
+            return Ok(None);
+        }
+        let stream_id = u16::from_le_bytes(datagram[0..2].try_into().unwrap());
+        let seq = u32::from_le_bytes(datagram[2..6].try_into().unwrap());
+        let index = usize::from(u16::from_le_bytes(datagram[6..8].try_into().unwrap()));
+        let count = usize::from(u16::from_le_bytes(datagram[8..10].try_into().unwrap()));
+        if stream_id != self.stream_id || index >= count {
+            return Ok(None);
+        }
+        match self.seq {
+            Some(current) if current == seq => {}
+            // the sequence number is compared with wrapping, older fragments are ignored
+            Some(current) if seq.wrapping_sub(current) > u32::MAX / 2 => return Ok(None),
+            current => {
+                if current.is_some() && self.received > 0 {
+                    trace!(
+                        stream_id,
+                        seq = current,
+                        "incomplete multicast frame dropped"
+                    );
+                }
+                self.seq = Some(seq);
+                self.chunks.clear();
+                self.chunks.resize(count, None);
+                self.received = 0;
+            }
+        }
+        if self.chunks.len() != count {
+            return Ok(None);
+        }
+        if self.chunks[index].is_none() {
+            self.chunks[index] = Some(datagram[HEADER_SIZE..].to_vec());
+            self.received += 1;
+        }
+        if self.received < count {
+            return Ok(None);
+        }
+        let payload: Vec<u8> = self.chunks.drain(..).flatten().flatten().collect();
+        self.received = 0;
+        parse_payload(&payload).map(Some)
+    }
+}
+
+/// Parses a reassembled payload. Lengths come from unauthenticated datagrams, so they are checked
+/// against the payload size before allocating.
+fn parse_payload(payload: &[u8]) -> Result<Frame, Error> {
+    let mut cursor = io::Cursor::new(payload);
+    let remaining = |cursor: &io::Cursor<&[u8]>| {
+        payload
+            .len()
+            .saturating_sub(usize::try_from(cursor.position()).unwrap_or(usize::MAX))
+    };
+    let mut len_buf = [0u8; 4];
+    cursor.read_exact(&mut len_buf)?;
+    let len =
+        usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::FrameMetaDataTooLarge)?;
+    if len > remaining(&cursor) {
+        return Err(Error::FrameMetaDataTooLarge);
+    }
+    let metadata = if len > 0 {
+        let mut buf = vec![0u8; len];
+        cursor.read_exact(&mut buf)?;
+        Some(buf.into())
+    } else {
+        None
+    };
+    cursor.read_exact(&mut len_buf)?;
+    let len = usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::FrameDataTooLarge)?;
+    if len > remaining(&cursor) {
+        return Err(Error::FrameDataTooLarge);
+    }
+    let mut data = vec![0u8; len];
+    cursor.read_exact(&mut data)?;
+    Ok(Frame {
+        metadata,
+        data: data.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{pack_datagrams, parse_payload, Reassembler, CHUNK_SIZE};
+    use crate::Error;
+    use crate::Frame;
+
+    fn datagrams(stream_id: u16, seq: u32, frame: &Frame) -> Vec<Vec<u8>> {
+        let mut datagrams = Vec::new();
+        pack_datagrams(stream_id, seq, frame, |datagram| {
+            datagrams.push(datagram.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        datagrams
+    }
+
+    fn frame(n: u8) -> Frame {
+        let data: Vec<u8> = (0..CHUNK_SIZE * 3)
+            .map(|i| (i as u8).wrapping_add(n))
+            .collect();
+        let mut frame = Frame::from(data);
+        frame.metadata = Some(Arc::new(vec![n; 10]));
+        frame
+    }
+
+    fn assert_frame(received: Option<Frame>, n: u8) {
+        let received = received.expect("frame not complete");
+        let expected = frame(n);
+        assert_eq!(received.data, expected.data);
+        assert_eq!(received.metadata, expected.metadata);
+    }
+
+    #[test]
+    fn test_in_order() {
+        let mut reassembler = Reassembler::new(1);
+        let datagrams = datagrams(1, 0, &frame(0));
+        assert_eq!(datagrams.len(), 4);
+        let (last, rest) = datagrams.split_last().unwrap();
+        for datagram in rest {
+            assert!(reassembler.push(datagram).unwrap().is_none());
+        }
+        assert_frame(reassembler.push(last).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let mut reassembler = Reassembler::new(1);
+        let datagrams = datagrams(1, 7, &frame(7));
+        for i in [2, 0, 3] {
+            assert!(reassembler.push(&datagrams[i]).unwrap().is_none());
+        }
+        // duplicates are ignored
+        assert!(reassembler.push(&datagrams[0]).unwrap().is_none());
+        assert_frame(reassembler.push(&datagrams[1]).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_lost_fragment() {
+        let mut reassembler = Reassembler::new(1);
+        let first = datagrams(1, 1, &frame(1));
+        let second = datagrams(1, 2, &frame(2));
+        // the second fragment of the first frame is lost
+        for i in [0, 2, 3] {
+            assert!(reassembler.push(&first[i]).unwrap().is_none());
+        }
+        let (last, rest) = second.split_last().unwrap();
+        for datagram in rest {
+            assert!(reassembler.push(datagram).unwrap().is_none());
+        }
+        // the lost fragment, received late, is ignored
+        assert!(reassembler.push(&first[1]).unwrap().is_none());
+        assert_frame(reassembler.push(last).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_invalid_lengths() {
+        let mut payload = Vec::new();
+        payload.extend(u32::MAX.to_le_bytes());
+        assert!(matches!(
+            parse_payload(&payload),
+            Err(Error::FrameMetaDataTooLarge)
+        ));
+        payload.clear();
+        payload.extend(0u32.to_le_bytes());
+        payload.extend(5u32.to_le_bytes());
+        payload.extend([1, 2, 3, 4]);
+        assert!(matches!(
+            parse_payload(&payload),
+            Err(Error::FrameDataTooLarge)
+        ));
+        payload.push(5);
+        assert_eq!(&parse_payload(&payload).unwrap().data[..], &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_foreign_datagrams() {
+        let mut reassembler = Reassembler::new(1);
+        for datagram in datagrams(2, 0, &frame(0)) {
+            assert!(reassembler.push(&datagram).unwrap().is_none());
+        }
+        assert!(reassembler.push(&[0; 4]).unwrap().is_none());
+    }
+}
@@ -0,0 +1,313 @@
+use std::{io::Cursor, sync::Arc};
+
+use binrw::{binrw, BinWrite};
+use serde::{Deserialize, Serialize};
+
+use crate::{server::StreamServerInner, Error, StreamDescription, StreamInfo};
+
+/// Parameter change handler
+pub type ParamHandler = Arc<dyn Fn(&ParamValue) + Send + Sync>;
+
+/// Parameter value
+#[binrw]
+#[brw(little)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum ParamValue {
+    /// Boolean flag
+    #[brw(magic = 0u8)]
+    Bool(
+        #[br(map = |v: u8| v != 0)]
+        #[bw(map = |v: &bool| u8::from(*v))]
+        bool,
+    ),
+    /// Signed integer
+    #[brw(magic = 1u8)]
+    Int(i64),
+    /// Floating point number
+    #[brw(magic = 2u8)]
+    Float(f64),
+}
+
+impl ParamValue {
+    /// Get the value as a boolean (if the parameter is a flag)
+    pub fn as_bool(&self) -> Option<bool> {
+        if let ParamValue::Bool(v) = self {
+            Some(*v)
+        } else {
+            None
+        }
+    }
+    /// Get the value as an integer (if the parameter is an integer)
+    pub fn as_i64(&self) -> Option<i64> {
+        if let ParamValue::Int(v) = self {
+            Some(*v)
+        } else {
+            None
+        }
+    }
+    /// Get the value as a float (integers are converted)
+    #[allow(clippy::cast_precision_loss)]
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            ParamValue::Int(v) => Some(*v as f64),
+            ParamValue::Float(v) => Some(*v),
+            ParamValue::Bool(_) => None,
+        }
+    }
+}
+
+/// Parameter type and value range
+#[binrw]
+#[brw(little)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum ParamKind {
+    /// Boolean flag
+    #[brw(magic = 0u8)]
+    Bool,
+    /// Signed integer in the given range (inclusive)
+    #[brw(magic = 1u8)]
+    Int {
+        /// Minimum value
+        min: i64,
+        /// Maximum value
+        max: i64,
+    },
+    /// Floating point number in the given range (inclusive)
+    #[brw(magic = 2u8)]
+    Float {
+        /// Minimum value
+        min: f64,
+        /// Maximum value
+        max: f64,
+    },
+}
+
+impl ParamKind {
+    /// Check if the value matches the parameter type and range
+    pub fn check(&self, value: &ParamValue) -> Result<(), Error> {
+        let valid = match (self, value) {
+            (ParamKind::Bool, ParamValue::Bool(_)) => true,
+            (ParamKind::Int { min, max }, ParamValue::Int(v)) => v >= min && v <= max,
+            (ParamKind::Float { min, max }, ParamValue::Float(v)) => v >= min && v <= max,
+            _ => false,
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(Error::InvalidParamValue)
+        }
+    }
+}
+
+/// Parameter information, as reported to clients
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ParamInfo {
+    /// Parameter name
+    pub name: String,
+    /// Parameter type and value range
+    pub kind: ParamKind,
+    /// Current value
+    pub value: ParamValue,
+}
+
+/// A parameter helper object. Contains a parameter id and a reference to the server inner object
+#[derive(Clone)]
+pub struct Param {
+    pub(crate) id: usize,
+    pub(crate) server_inner: Arc<StreamServerInner>,
+}
+
+impl Param {
+    /// Get the parameter information
+    pub fn info(&self) -> ParamInfo {
+        self.server_inner.param_info(self.id)
+    }
+    /// Get the current parameter value
+    pub fn value(&self) -> ParamValue {
+        self.server_inner.param_info(self.id).value
+    }
+    /// Set the parameter value. The change handler is called as well.
+    pub fn set(&self, value: ParamValue) -> Result<(), Error> {
+        self.server_inner.set_param(self.id, value)
+    }
+    /// Set a handler, which is called when the parameter value is changed (either locally or by
+    /// a client). The handler is called in the thread which has changed the value so it should
+    /// not block.
+    pub fn on_change(&self, handler: impl Fn(&ParamValue) + Send + Sync + 'static) {
+        self.server_inner
+            .set_param_handler(self.id, Arc::new(handler));
+    }
+}
+
+pub(crate) struct ParamInternal {
+    pub(crate) info: ParamInfo,
+    pub(crate) handler: Option<ParamHandler>,
+}
+
+pub(crate) const PARAM_STATUS_OK: u8 = 0;
+pub(crate) const PARAM_STATUS_NOT_FOUND: u8 = 1;
+pub(crate) const PARAM_STATUS_INVALID_VALUE: u8 = 2;
+
+pub(crate) fn param_status(error: &Error) -> u8 {
+    match error {
+        Error::ParamNotFound => PARAM_STATUS_NOT_FOUND,
+        _ => PARAM_STATUS_INVALID_VALUE,
+    }
+}
+
+/// Checks a control response status and returns the response payload
+pub(crate) fn control_response_payload(response: &[u8]) -> Result<&[u8], Error> {
+    let (status, payload) = response.split_first().ok_or(Error::InvalidParam)?;
+    match *status {
+        PARAM_STATUS_OK => Ok(payload),
+        PARAM_STATUS_NOT_FOUND => Err(Error::ParamNotFound),
+        PARAM_STATUS_INVALID_VALUE => Err(Error::InvalidParamValue),
+        _ => Err(Error::InvalidParam),
+    }
+}
+
+/// Packs a structure into a binary buffer
+pub(crate) fn pack<T>(data: &T) -> Result<Vec<u8>, Error>
+where
+    T: for<'a> BinWrite<Args<'a> = ()>,
+{
+    let mut writer = Cursor::new(Vec::new());
+    data.write_le(&mut writer)?;
+    Ok(writer.into_inner())
+}
+
+/// Packs a control request, prefixed with its length
+pub(crate) fn pack_control_request(request: &ControlRequest) -> Result<Vec<u8>, Error> {
+    let body = pack(request)?;
+    let len = u32::try_from(body.len()).map_err(|_| Error::InvalidParam)?;
+    let mut buf = Vec::with_capacity(body.len() + 4);
+    buf.extend(len.to_le_bytes());
+    buf.extend(body);
+    Ok(buf)
+}
+
+/// Length-prefixed UTF-8 string
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) struct ParamName {
+    #[bw(try_calc = u8::try_from(name.len()))]
+    len: u8,
+    #[br(count = len, try_map = String::from_utf8)]
+    #[bw(map = |s: &String| s.as_bytes().to_vec())]
+    pub(crate) name: String,
+}
+
+impl ParamName {
+    pub(crate) fn new(name: &str) -> Result<Self, Error> {
+        if name.is_empty() || name.len() > usize::from(u8::MAX) {
+            return Err(Error::InvalidParam);
+        }
+        Ok(Self {
+            name: name.to_owned(),
+        })
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) struct ParamEntry {
+    pub(crate) name: ParamName,
+    pub(crate) kind: ParamKind,
+    pub(crate) value: ParamValue,
+}
+
+impl From<ParamEntry> for ParamInfo {
+    fn from(entry: ParamEntry) -> Self {
+        Self {
+            name: entry.name.name,
+            kind: entry.kind,
+            value: entry.value,
+        }
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) struct ParamList {
+    #[bw(try_calc = u16::try_from(params.len()))]
+    count: u16,
+    #[br(count = count)]
+    pub(crate) params: Vec<ParamEntry>,
+}
+
+impl ParamList {
+    pub(crate) fn new(params: Vec<ParamEntry>) -> Self {
+        Self { params }
+    }
+}
+
+/// Stream name, empty if not set
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) struct StreamName {
+    #[bw(try_calc = u8::try_from(name.len()))]
+    len: u8,
+    #[br(count = len, try_map = String::from_utf8)]
+    #[bw(map = |s: &String| s.as_bytes().to_vec())]
+    pub(crate) name: String,
+}
+
+impl StreamName {
+    pub(crate) fn new(name: Option<&str>) -> Self {
+        Self {
+            name: name.unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) struct StreamEntry {
+    pub(crate) info: StreamInfo,
+    pub(crate) name: StreamName,
+}
+
+impl From<StreamEntry> for StreamDescription {
+    fn from(entry: StreamEntry) -> Self {
+        Self {
+            info: entry.info,
+            name: Some(entry.name.name).filter(|name| !name.is_empty()),
+        }
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) struct StreamList {
+    #[bw(try_calc = u16::try_from(streams.len()))]
+    count: u16,
+    #[br(count = count)]
+    pub(crate) streams: Vec<StreamEntry>,
+}
+
+impl StreamList {
+    pub(crate) fn new(streams: Vec<StreamEntry>) -> Self {
+        Self { streams }
+    }
+}
+
+/// Control session requests, sent by clients
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+pub(crate) enum ControlRequest {
+    #[brw(magic = 0x10u8)]
+    ListParams,
+    #[brw(magic = 0x11u8)]
+    GetParam(ParamName),
+    #[brw(magic = 0x12u8)]
+    SetParam(ParamName, ParamValue),
+    #[brw(magic = 0x13u8)]
+    ListStreams,
+}
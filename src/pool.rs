@@ -0,0 +1,199 @@
+//! Frame buffer pools: pre-allocated frame data buffers, which are reused once sent frames are
+//! released by all clients
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use tracing::trace;
+
+use crate::{Frame, Mutex};
+
+struct PoolInner {
+    // buffers, handed out as frames, are kept to be reclaimed when released by clients
+    buffers: Mutex<Vec<Arc<Vec<u8>>>>,
+    buffer_size: usize,
+    // MJPEG buffers are empty with the capacity reserved
+    fixed_size: bool,
+    allocated: AtomicUsize,
+}
+
+impl PoolInner {
+    fn allocate(&self) -> Vec<u8> {
+        self.allocated.fetch_add(1, Ordering::Relaxed);
+        if self.fixed_size {
+            vec![0; self.buffer_size]
+        } else {
+            Vec::with_capacity(self.buffer_size)
+        }
+    }
+}
+
+/// A pool of frame data buffers, which allows to publish frames without allocations in real-time
+/// code. A buffer, sent as a frame, is reclaimed automatically when the frame is released by the
+/// server and all clients (e.g. written to sockets or replaced by newer frames). If all buffers
+/// are in use, the pool grows.
+///
+/// Pools are usually created with [`crate::Stream::frame_pool`]: buffers of raw streams have the
+/// frame size, buffers of MJPEG streams are empty with the capacity of an RGB8 picture
+/// (width * height * 3) reserved.
+/// Reused buffers contain data of previous frames.
+#[derive(Clone)]
+pub struct FramePool {
+    inner: Arc<PoolInner>,
+}
+
+impl FramePool {
+    /// Create a new pool of buffers of the given size
+    pub fn new(buffer_size: usize, buffers: usize) -> Self {
+        Self::create(buffer_size, true, buffers)
+    }
+    /// Create a new pool of empty buffers with the given capacity reserved (for compressed
+    /// frames, which size varies)
+    pub fn with_capacity(capacity: usize, buffers: usize) -> Self {
+        Self::create(capacity, false, buffers)
+    }
+    pub(crate) fn create(buffer_size: usize, fixed_size: bool, buffers: usize) -> Self {
+        let inner = PoolInner {
+            buffers: <_>::default(),
+            buffer_size,
+            fixed_size,
+            allocated: AtomicUsize::new(0),
+        };
+        let preallocated = (0..buffers).map(|_| Arc::new(inner.allocate())).collect();
+        *inner.buffers.lock() = preallocated;
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+    /// Get a free buffer (allocated if all buffers are in use)
+    pub fn get(&self) -> FrameBuffer {
+        let mut buffers = self.inner.buffers.lock();
+        // a buffer is free if it is referenced by the pool only
+        let data = if let Some(pos) = buffers.iter().position(|b| Arc::strong_count(b) == 1) {
+            buffers.swap_remove(pos)
+        } else {
+            trace!(
+                buffers = self.inner.allocated.load(Ordering::Relaxed) + 1,
+                "frame pool grows"
+            );
+            Arc::new(self.inner.allocate())
+        };
+        FrameBuffer {
+            data: Some(data),
+            pool: self.inner.clone(),
+        }
+    }
+    /// The total number of buffers, including ones in use
+    pub fn buffer_count(&self) -> usize {
+        self.inner.allocated.load(Ordering::Relaxed)
+    }
+    /// The number of free buffers
+    pub fn free_count(&self) -> usize {
+        self.inner
+            .buffers
+            .lock()
+            .iter()
+            .filter(|b| Arc::strong_count(b) == 1)
+            .count()
+    }
+}
+
+/// A frame data buffer of [`FramePool`]. Dereferences to the data vector, which can be modified
+/// in place. Converted into a frame to be sent, returned to the pool if dropped.
+pub struct FrameBuffer {
+    data: Option<Arc<Vec<u8>>>,
+    pool: Arc<PoolInner>,
+}
+
+impl FrameBuffer {
+    /// Convert the buffer into a frame
+    pub fn into_frame(self) -> Frame {
+        self.into()
+    }
+    /// Convert the buffer into a frame with metadata
+    pub fn into_frame_with_metadata(self, metadata: Arc<Vec<u8>>) -> Frame {
+        let mut frame: Frame = self.into();
+        frame.metadata = Some(metadata);
+        frame
+    }
+    fn take(&mut self) -> Arc<Vec<u8>> {
+        self.data.take().expect("frame buffer already taken")
+    }
+}
+
+impl Deref for FrameBuffer {
+    type Target = Vec<u8>;
+    fn deref(&self) -> &Self::Target {
+        self.data.as_ref().expect("frame buffer already taken")
+    }
+}
+
+impl DerefMut for FrameBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // the buffer is referenced by the handle only until converted into a frame
+        Arc::get_mut(self.data.as_mut().expect("frame buffer already taken"))
+            .expect("frame buffer is shared")
+    }
+}
+
+impl From<FrameBuffer> for Frame {
+    fn from(mut buffer: FrameBuffer) -> Self {
+        let data = buffer.take();
+        buffer.pool.buffers.lock().push(data.clone());
+        Frame::new(data)
+    }
+}
+
+impl Drop for FrameBuffer {
+    fn drop(&mut self) {
+        if let Some(data) = self.data.take() {
+            self.pool.buffers.lock().push(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FramePool;
+
+    #[test]
+    fn test_reuse() {
+        let pool = FramePool::new(4, 1);
+        assert_eq!(pool.buffer_count(), 1);
+        let mut buffer = pool.get();
+        buffer.copy_from_slice(&[1, 2, 3, 4]);
+        let ptr = buffer.as_ptr();
+        let frame = buffer.into_frame();
+        let clone = frame.clone();
+        assert_eq!(pool.free_count(), 0);
+        // the buffer is in use, the pool grows
+        let other = pool.get();
+        assert_eq!(pool.buffer_count(), 2);
+        drop(other);
+        drop(frame);
+        assert_eq!(pool.free_count(), 1);
+        drop(clone);
+        assert_eq!(pool.free_count(), 2);
+        // the released buffer is reused, with data of the previous frame
+        let first = pool.get();
+        let second = pool.get();
+        assert_eq!(pool.buffer_count(), 2);
+        let reused = if first.as_ptr() == ptr { first } else { second };
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(&reused[..], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_unsent_buffer() {
+        let pool = FramePool::new(4, 0);
+        drop(pool.get());
+        assert_eq!(pool.buffer_count(), 1);
+        assert_eq!(pool.free_count(), 1);
+        drop(pool.get());
+        assert_eq!(pool.buffer_count(), 1);
+    }
+}
@@ -0,0 +1,58 @@
+//! Publish mode: a stream is pushed by a background client to a hub server
+use std::{net::SocketAddr, thread, time::Duration};
+
+use tracing::{trace, warn};
+
+use crate::{Client, Error, Stream, StreamInfo};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+pub(crate) struct Publisher {
+    pub(crate) addr: SocketAddr,
+    pub(crate) secret: Option<Vec<u8>>,
+    pub(crate) timeout: Duration,
+}
+
+impl Publisher {
+    fn connect(&self, info: &StreamInfo, name: &str) -> Result<Client, Error> {
+        let mut client = Client::connect(self.addr, self.timeout)?;
+        if let Some(ref secret) = self.secret {
+            client = client.with_secret(secret);
+        }
+        let hub_stream_id = client.publish(info, name)?;
+        trace!(addr = %self.addr, stream_id = info.id, hub_stream_id, "stream published");
+        Ok(client)
+    }
+    /// Starts a background worker, which receives frames of the stream as a local client and
+    /// pushes them to the hub. Keepalives are sent if the stream is idle.
+    pub(crate) fn spawn(self, stream: Stream) -> Result<(), Error> {
+        let name = stream.name().ok_or(Error::InvalidStreamName)?;
+        let info = stream.server_inner.stream_info(stream.id)?;
+        let client_id = stream.server_inner.next_client_id();
+        let rx = stream.server_inner.add_client(stream.id, client_id, None)?;
+        let keepalive_interval = self.timeout / 2;
+        thread::spawn(move || loop {
+            let mut client = match self.connect(&info, &name) {
+                Ok(client) => client,
+                Err(error) => {
+                    warn!(addr = %self.addr, stream_id = info.id, %error, "unable to publish stream");
+                    thread::sleep(RECONNECT_DELAY);
+                    continue;
+                }
+            };
+            loop {
+                let result = match rx.get_timeout(keepalive_interval) {
+                    Ok(frame) => client.push_frame(&frame),
+                    Err(rtsc::Error::Timeout) => client.push_keepalive(),
+                    // the stream worker is removed
+                    Err(_) => return,
+                };
+                if let Err(error) = result {
+                    warn!(addr = %self.addr, stream_id = info.id, %error, "publishing connection lost");
+                    break;
+                }
+            }
+        });
+        Ok(())
+    }
+}
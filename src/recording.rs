@@ -0,0 +1,473 @@
+//! Stream recordings (see `recording.md` for the file format description)
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use binrw::{binrw, BinRead};
+
+use crate::{
+    params::{pack, StreamName},
+    read_frame_blocks_limited, write_frame_blocks, Error, Frame, StreamInfo,
+};
+
+const RECORDING_VERSION: u8 = 1;
+
+const RECORD_FRAME: u8 = 0x01;
+const RECORD_INDEX: u8 = 0x02;
+
+const TRAILER_MAGIC: &[u8; 4] = b"RVIX";
+const TRAILER_SIZE: u64 = 12;
+
+#[binrw]
+#[brw(little, magic = b"RVREC")]
+#[derive(Clone, Debug)]
+struct RecordingHeader {
+    version: u8,
+    info: StreamInfo,
+    name: StreamName,
+    // microseconds since UNIX epoch
+    started: u64,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+struct IndexEntry {
+    // microseconds since the recording start
+    timestamp: u64,
+    offset: u64,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Clone, Debug)]
+struct Index {
+    #[bw(try_calc = u32::try_from(entries.len()))]
+    count: u32,
+    #[br(count = count)]
+    entries: Vec<IndexEntry>,
+}
+
+fn micros(d: Duration) -> u64 {
+    u64::try_from(d.as_micros()).unwrap_or(u64::MAX)
+}
+
+/// A recorded frame
+#[derive(Clone, Debug)]
+pub struct RecordedFrame {
+    /// Time since the recording start
+    pub timestamp: Duration,
+    /// The frame
+    pub frame: Frame,
+}
+
+/// Writes a stream into a recording file. The seek index is written when the recorder is
+/// finished (or dropped). Recordings, which have not been finished (e.g. after a crash), are
+/// still readable, the index is rebuilt by the reader.
+pub struct Recorder {
+    file: BufWriter<File>,
+    position: u64,
+    index: Vec<IndexEntry>,
+    started: Instant,
+    finished: bool,
+    // a write has failed, the file may end with an incomplete record
+    failed: bool,
+}
+
+impl Recorder {
+    /// Create a new recording file for the stream
+    pub fn create(
+        path: impl AsRef<Path>,
+        info: &StreamInfo,
+        name: Option<&str>,
+    ) -> Result<Self, Error> {
+        if name.map_or(false, |name| name.len() > usize::from(u8::MAX)) {
+            return Err(Error::InvalidStreamName);
+        }
+        let header = pack(&RecordingHeader {
+            version: RECORDING_VERSION,
+            info: info.clone(),
+            name: StreamName::new(name),
+            started: micros(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default(),
+            ),
+        })?;
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header)?;
+        Ok(Self {
+            file,
+            position: header.len() as u64,
+            index: Vec::new(),
+            started: Instant::now(),
+            finished: false,
+            failed: false,
+        })
+    }
+    /// Write a frame, timestamped with the time elapsed since the recording start
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.write_frame_at(frame, self.started.elapsed())
+    }
+    /// Write a frame with a custom timestamp (time since the recording start). Timestamps must
+    /// not decrease. After a failed write, the recorder rejects further frames (the recording
+    /// stays readable up to the last complete frame).
+    pub fn write_frame_at(&mut self, frame: &Frame, timestamp: Duration) -> Result<(), Error> {
+        if self.finished || self.failed {
+            return Err(Error::NotReady);
+        }
+        let timestamp = micros(timestamp);
+        if self.index.last().map_or(false, |e| e.timestamp > timestamp) {
+            return Err(Error::InvalidRecording);
+        }
+        let metadata_len = frame.metadata.as_ref().map_or(0, |v| v.len());
+        if u32::try_from(metadata_len).is_err() {
+            return Err(Error::FrameMetaDataTooLarge);
+        }
+        if u32::try_from(frame.data.len()).is_err() {
+            return Err(Error::FrameDataTooLarge);
+        }
+        // the frame is written into the buffered file as-is, without being copied into a record
+        let mut header = [0u8; 9];
+        header[0] = RECORD_FRAME;
+        header[1..].copy_from_slice(&timestamp.to_le_bytes());
+        if let Err(error) = self
+            .file
+            .write_all(&header)
+            .map_err(Error::from)
+            .and_then(|()| write_frame_blocks(&mut self.file, frame))
+        {
+            self.failed = true;
+            return Err(error);
+        }
+        self.index.push(IndexEntry {
+            timestamp,
+            offset: self.position,
+        });
+        self.position += (header.len() + 8 + metadata_len + frame.data.len()) as u64;
+        Ok(())
+    }
+    /// Number of frames written
+    pub fn frame_count(&self) -> usize {
+        self.index.len()
+    }
+    /// Number of bytes written
+    pub fn size(&self) -> u64 {
+        self.position
+    }
+    /// Write the seek index and close the recording
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.write_index()
+    }
+    fn write_index(&mut self) -> Result<(), Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if self.failed {
+            // the index position is unknown, the reader rebuilds the index
+            self.file.flush()?;
+            return Ok(());
+        }
+        let mut buf = vec![RECORD_INDEX];
+        buf.extend(pack(&Index {
+            entries: std::mem::take(&mut self.index),
+        })?);
+        buf.extend(self.position.to_le_bytes());
+        buf.extend(TRAILER_MAGIC);
+        self.file.write_all(&buf)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _r = self.write_index();
+    }
+}
+
+/// Reads recordings, created by [`Recorder`]
+pub struct RecordingReader {
+    file: BufReader<File>,
+    info: StreamInfo,
+    name: Option<String>,
+    started: SystemTime,
+    index: Vec<IndexEntry>,
+    next_frame: usize,
+    len: u64,
+}
+
+impl RecordingReader {
+    /// Open a recording file
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut file = BufReader::new(File::open(path)?);
+        let header = RecordingHeader::read(&mut file).map_err(|_| Error::InvalidRecording)?;
+        if header.version != RECORDING_VERSION {
+            return Err(Error::InvalidRecording);
+        }
+        let data_start = file.stream_position()?;
+        let len = file.seek(SeekFrom::End(0))?;
+        let index = match read_index(&mut file, data_start, len)? {
+            Some(index) => index,
+            None => scan_index(&mut file, data_start, len)?,
+        };
+        let mut reader = Self {
+            file,
+            info: header.info,
+            name: Some(header.name.name).filter(|name| !name.is_empty()),
+            started: UNIX_EPOCH + Duration::from_micros(header.started),
+            index,
+            next_frame: 0,
+            len,
+        };
+        reader.seek_frame(0)?;
+        Ok(reader)
+    }
+    /// Recorded stream information
+    pub fn stream_info(&self) -> &StreamInfo {
+        &self.info
+    }
+    /// Recorded stream name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    /// Recording start time
+    pub fn started(&self) -> SystemTime {
+        self.started
+    }
+    /// Number of recorded frames
+    pub fn frame_count(&self) -> usize {
+        self.index.len()
+    }
+    /// Recording duration (the timestamp of the last frame)
+    pub fn duration(&self) -> Duration {
+        self.index
+            .last()
+            .map_or(Duration::ZERO, |e| Duration::from_micros(e.timestamp))
+    }
+    /// Number of the frame, which is read next
+    pub fn position(&self) -> usize {
+        self.next_frame
+    }
+    /// Seek to a frame by its number (seeking to the frame count moves to the end of the
+    /// recording)
+    pub fn seek_frame(&mut self, frame_number: usize) -> Result<(), Error> {
+        match self.index.get(frame_number) {
+            Some(entry) => {
+                self.file.seek(SeekFrom::Start(entry.offset))?;
+            }
+            None if frame_number == self.index.len() => {}
+            None => return Err(Error::InvalidRecording),
+        }
+        self.next_frame = frame_number;
+        Ok(())
+    }
+    /// Seek to the first frame, which timestamp is equal or greater than the given one
+    pub fn seek_time(&mut self, timestamp: Duration) -> Result<(), Error> {
+        let timestamp = micros(timestamp);
+        let frame_number = self.index.partition_point(|e| e.timestamp < timestamp);
+        self.seek_frame(frame_number)
+    }
+    /// Read the next frame, returns `None` at the end of the recording
+    pub fn read_frame(&mut self) -> Result<Option<RecordedFrame>, Error> {
+        if self.next_frame >= self.index.len() {
+            return Ok(None);
+        }
+        let (timestamp, frame) = read_frame_record(&mut self.file, self.len)?;
+        self.next_frame += 1;
+        Ok(Some(RecordedFrame {
+            timestamp: Duration::from_micros(timestamp),
+            frame,
+        }))
+    }
+}
+
+impl Iterator for RecordingReader {
+    type Item = Result<RecordedFrame, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Reads a frame record. Block lengths are checked against the remaining file size before
+/// allocating, so corrupted files can not cause huge allocations.
+fn read_frame_record(file: &mut BufReader<File>, len: u64) -> Result<(u64, Frame), Error> {
+    let mut buf = [0u8; 9];
+    file.read_exact(&mut buf)?;
+    if buf[0] != RECORD_FRAME {
+        return Err(Error::InvalidRecording);
+    }
+    let timestamp = u64::from_le_bytes(buf[1..].try_into().unwrap());
+    let remaining =
+        usize::try_from(len.saturating_sub(file.stream_position()?)).unwrap_or(usize::MAX);
+    Ok((
+        timestamp,
+        read_frame_blocks_limited(file, remaining, remaining).map_err(|error| match error {
+            Error::FrameMetaDataTooLarge | Error::FrameDataTooLarge => Error::InvalidRecording,
+            error => error,
+        })?,
+    ))
+}
+
+/// Reads the index, written by the recorder, returns `None` if there is no valid one
+fn read_index(
+    file: &mut BufReader<File>,
+    data_start: u64,
+    len: u64,
+) -> Result<Option<Vec<IndexEntry>>, Error> {
+    if len < data_start + TRAILER_SIZE + 1 {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-(TRAILER_SIZE as i64)))?;
+    let mut trailer = [0u8; TRAILER_SIZE as usize];
+    file.read_exact(&mut trailer)?;
+    if &trailer[8..] != TRAILER_MAGIC {
+        return Ok(None);
+    }
+    let offset = u64::from_le_bytes(trailer[..8].try_into().unwrap());
+    if offset < data_start || offset >= len - TRAILER_SIZE {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = vec![0u8; usize::try_from(len - TRAILER_SIZE - offset).unwrap()];
+    file.read_exact(&mut buf)?;
+    // the index record contains the type, the entry count and 16-byte entries
+    if buf.len() < 5 || buf[0] != RECORD_INDEX {
+        return Ok(None);
+    }
+    let count = u32::from_le_bytes(buf[1..5].try_into().unwrap());
+    if u64::from(count) * 16 != buf.len() as u64 - 5 {
+        return Ok(None);
+    }
+    Ok(Index::read(&mut Cursor::new(&buf[1..]))
+        .ok()
+        .map(|index| index.entries))
+}
+
+/// Rebuilds the index of a recording, which has not been finished. An incomplete last frame is
+/// ignored.
+fn scan_index(
+    file: &mut BufReader<File>,
+    data_start: u64,
+    len: u64,
+) -> Result<Vec<IndexEntry>, Error> {
+    let mut index = Vec::new();
+    let mut offset = data_start;
+    file.seek(SeekFrom::Start(offset))?;
+    while let Ok((timestamp, _)) = read_frame_record(file, len) {
+        index.push(IndexEntry { timestamp, offset });
+        offset = file.stream_position()?;
+    }
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, sync::Arc, time::Duration};
+
+    use super::{Recorder, RecordingReader};
+    use crate::{Format, Frame, StreamInfo};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rvideo-test-{}-{}.rvr", std::process::id(), name))
+    }
+
+    fn info() -> StreamInfo {
+        StreamInfo {
+            id: 0,
+            format: Format::Luma8,
+            width: 4,
+            height: 2,
+        }
+    }
+
+    fn frame(n: u8) -> Frame {
+        let mut frame = Frame::from(vec![n; 8]);
+        if n % 2 == 0 {
+            frame.metadata = Some(Arc::new(vec![n, n + 1]));
+        }
+        frame
+    }
+
+    fn write_recording(path: &PathBuf, frames: u8) {
+        let mut recorder = Recorder::create(path, &info(), Some("test")).unwrap();
+        for n in 0..frames {
+            recorder
+                .write_frame_at(&frame(n), Duration::from_millis(u64::from(n) * 100))
+                .unwrap();
+        }
+        assert_eq!(recorder.frame_count(), usize::from(frames));
+        recorder.finish().unwrap();
+    }
+
+    fn assert_frames(reader: RecordingReader, frames: u8) {
+        let recorded: Vec<_> = reader.map(Result::unwrap).collect();
+        assert_eq!(recorded.len(), usize::from(frames));
+        for (n, recorded) in (0..frames).zip(recorded) {
+            let expected = frame(n);
+            assert_eq!(
+                recorded.timestamp,
+                Duration::from_millis(u64::from(n) * 100)
+            );
+            assert_eq!(recorded.frame.data, expected.data);
+            assert_eq!(recorded.frame.metadata, expected.metadata);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let path = temp_path("round-trip");
+        write_recording(&path, 5);
+        let mut reader = RecordingReader::open(&path).unwrap();
+        assert_eq!(reader.name(), Some("test"));
+        assert_eq!(reader.stream_info().format, Format::Luma8);
+        assert_eq!(reader.frame_count(), 5);
+        assert_eq!(reader.duration(), Duration::from_millis(400));
+        reader.seek_time(Duration::from_millis(250)).unwrap();
+        assert_eq!(reader.position(), 3);
+        assert_eq!(
+            reader.read_frame().unwrap().unwrap().frame.data,
+            frame(3).data
+        );
+        reader.seek_frame(0).unwrap();
+        assert_frames(reader, 5);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_scan_truncated() {
+        let path = temp_path("truncated");
+        write_recording(&path, 5);
+        // cut the index and a part of the last frame
+        let data = fs::read(&path).unwrap();
+        let last_frame_offset =
+            usize::try_from(RecordingReader::open(&path).unwrap().index[4].offset).unwrap();
+        fs::write(&path, &data[..last_frame_offset + 5]).unwrap();
+        let reader = RecordingReader::open(&path).unwrap();
+        assert_eq!(reader.frame_count(), 4);
+        assert_frames(reader, 4);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_length() {
+        let path = temp_path("corrupted");
+        write_recording(&path, 5);
+        let reader = RecordingReader::open(&path).unwrap();
+        let last_frame_offset = usize::try_from(reader.index[4].offset).unwrap();
+        drop(reader);
+        // cut the index, the metadata length of the last frame is set to 4 GiB
+        let mut data = fs::read(&path).unwrap();
+        data.truncate(last_frame_offset + 40);
+        data[last_frame_offset + 9..last_frame_offset + 13]
+            .copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, &data).unwrap();
+        let reader = RecordingReader::open(&path).unwrap();
+        assert_frames(reader, 4);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,95 @@
+//! Remote stream import: a local stream, fed by a background client of a remote server
+use std::{net::SocketAddr, sync::Arc, thread, time::Duration};
+
+use tracing::{trace, warn};
+
+use crate::{Client, Error, Stream, StreamDescription, StreamInfo};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+pub(crate) struct Remote {
+    pub(crate) addr: SocketAddr,
+    pub(crate) stream_id: u16,
+    pub(crate) max_fps: u8,
+    pub(crate) secret: Option<Vec<u8>>,
+    pub(crate) timeout: Duration,
+}
+
+impl Remote {
+    fn connect(&self) -> Result<Client, Error> {
+        let client = Client::connect(self.addr, self.timeout)?;
+        Ok(if let Some(ref secret) = self.secret {
+            client.with_secret(secret)
+        } else {
+            client
+        })
+    }
+    /// Gets the remote stream description
+    pub(crate) fn describe(&self) -> Result<StreamDescription, Error> {
+        self.connect()?
+            .streams()?
+            .into_iter()
+            .find(|s| s.info.id == self.stream_id)
+            .ok_or(Error::InvalidStream)
+    }
+    /// Connects to the remote stream, which must match the local one
+    fn select(&self, info: &StreamInfo) -> Result<Client, Error> {
+        let mut client = self.connect()?;
+        let remote_info = client.select_stream(self.stream_id, self.max_fps)?;
+        if remote_info.format != info.format
+            || remote_info.width != info.width
+            || remote_info.height != info.height
+        {
+            return Err(Error::InvalidStream);
+        }
+        Ok(client)
+    }
+    /// Starts a background worker, which feeds the local stream. If a remote connection is lost,
+    /// the worker reconnects immediately and marks the stream offline only if the remote server
+    /// is unavailable (so idle remote streams are not reported offline). The worker keeps a weak
+    /// reference to the local server and stops when the server is dropped.
+    pub(crate) fn spawn(self, info: StreamInfo, stream: Stream) {
+        let stream_id = stream.id;
+        let server = Arc::downgrade(&stream.server_inner);
+        drop(stream);
+        thread::spawn(move || {
+            while let Some(server_inner) = server.upgrade() {
+                match self.select(&info) {
+                    Ok(client) => {
+                        server_inner.set_stream_online(stream_id, true);
+                        drop(server_inner);
+                        for frame in client {
+                            let Some(server_inner) = server.upgrade() else {
+                                break;
+                            };
+                            match frame {
+                                Ok(frame) => {
+                                    let _r = server_inner.send_frame(stream_id, frame);
+                                }
+                                Err(error) => {
+                                    trace!(addr = %self.addr, %error, "remote stream connection lost");
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Err(error) => {
+                        if server_inner.stream_online(stream_id) {
+                            warn!(
+                                addr = %self.addr,
+                                remote_stream_id = self.stream_id,
+                                stream_id,
+                                %error,
+                                "remote stream offline"
+                            );
+                            server_inner.set_stream_online(stream_id, false);
+                        }
+                        drop(server_inner);
+                        thread::sleep(RECONNECT_DELAY);
+                    }
+                }
+            }
+            trace!(addr = %self.addr, stream_id, "remote stream worker stopped");
+        });
+    }
+}
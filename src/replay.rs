@@ -0,0 +1,195 @@
+//! Recording replay: a local stream, fed by a background worker from a recording file
+use std::{
+    ops::RangeInclusive,
+    sync::{Arc, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{trace, warn};
+
+use crate::{server::StreamServerInner, Condvar, Error, Mutex, RecordingReader, Stream};
+
+/// Supported speed factors
+const FACTOR_RANGE: RangeInclusive<f64> = 0.01..=1000.0;
+
+/// The worker re-checks if the stream is still alive while waiting
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Replay speed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// The original timing of the recording
+    Original,
+    /// The original timing, accelerated (or slowed down if less than 1.0) by the factor
+    /// (0.01-1000)
+    Factor(f64),
+    /// Frames are sent one by one, on [`Replay::step`] calls
+    Step,
+}
+
+impl ReplaySpeed {
+    pub(crate) fn validate(self) -> Result<Self, Error> {
+        match self {
+            ReplaySpeed::Factor(factor) if !FACTOR_RANGE.contains(&factor) => {
+                Err(Error::InvalidReplaySpeed)
+            }
+            speed => Ok(speed),
+        }
+    }
+}
+
+struct ReplayState {
+    speed: ReplaySpeed,
+    looped: bool,
+    steps: usize,
+    finished: bool,
+}
+
+struct ReplayControl {
+    state: Mutex<ReplayState>,
+    condvar: Condvar,
+}
+
+/// A replay helper object, returned by [`crate::Server::add_replay_stream`]. Contains the stream
+/// and allows to control the replay.
+#[derive(Clone)]
+pub struct Replay {
+    stream: Stream,
+    control: Arc<ReplayControl>,
+}
+
+impl Replay {
+    pub(crate) fn spawn(
+        reader: RecordingReader,
+        stream: Stream,
+        speed: ReplaySpeed,
+        looped: bool,
+    ) -> Result<Self, Error> {
+        let control = Arc::new(ReplayControl {
+            state: Mutex::new(ReplayState {
+                speed: speed.validate()?,
+                looped,
+                steps: 0,
+                finished: false,
+            }),
+            condvar: Condvar::new(),
+        });
+        let worker = Worker {
+            stream_id: stream.id,
+            server: Arc::downgrade(&stream.server_inner),
+            control: control.clone(),
+        };
+        thread::spawn(move || worker.run(reader));
+        Ok(Self { stream, control })
+    }
+    /// The stream, the recording is replayed into
+    pub fn stream(&self) -> &Stream {
+        &self.stream
+    }
+    /// Change the replay speed. The new speed is applied to the next frame.
+    pub fn set_speed(&self, speed: ReplaySpeed) -> Result<(), Error> {
+        self.control.state.lock().speed = speed.validate()?;
+        self.control.condvar.notify_all();
+        Ok(())
+    }
+    /// Replay the recording from the start again, when the end is reached. If looping is enabled
+    /// after the replay has been finished, it is not restarted.
+    pub fn set_looped(&self, looped: bool) {
+        self.control.state.lock().looped = looped;
+    }
+    /// Send the next frame (in [`ReplaySpeed::Step`] mode). Steps, requested while the previous
+    /// frame is still being read, are queued.
+    pub fn step(&self) {
+        self.control.state.lock().steps += 1;
+        self.control.condvar.notify_all();
+    }
+    /// Returns true if the end of the recording has been reached (and looping is disabled) or the
+    /// recording can not be read
+    pub fn is_finished(&self) -> bool {
+        self.control.state.lock().finished
+    }
+}
+
+/// The replay worker keeps a weak reference to the server and stops when the server is dropped
+struct Worker {
+    stream_id: u16,
+    server: Weak<StreamServerInner>,
+    control: Arc<ReplayControl>,
+}
+
+impl Worker {
+    fn run(self, mut reader: RecordingReader) {
+        // the due time and the timestamp of the previous frame
+        let mut prev: Option<(Instant, Duration)> = None;
+        loop {
+            let recorded = match reader.read_frame() {
+                Ok(Some(recorded)) => recorded,
+                Ok(None) if self.control.state.lock().looped && reader.frame_count() > 0 => {
+                    trace!(stream_id = self.stream_id, "replay restarted");
+                    if let Err(error) = reader.seek_frame(0) {
+                        warn!(stream_id = self.stream_id, %error, "replay failed");
+                        break;
+                    }
+                    prev = None;
+                    continue;
+                }
+                Ok(None) => {
+                    trace!(stream_id = self.stream_id, "replay finished");
+                    break;
+                }
+                Err(error) => {
+                    warn!(stream_id = self.stream_id, %error, "replay failed");
+                    break;
+                }
+            };
+            let mut state = self.control.state.lock();
+            loop {
+                let factor = match state.speed {
+                    ReplaySpeed::Step => {
+                        if state.steps > 0 {
+                            state.steps -= 1;
+                            prev = Some((Instant::now(), recorded.timestamp));
+                            break;
+                        }
+                        self.control
+                            .condvar
+                            .wait_for(&mut state, IDLE_CHECK_INTERVAL);
+                        if self.server.strong_count() == 0 {
+                            break;
+                        }
+                        continue;
+                    }
+                    ReplaySpeed::Original => 1.0,
+                    ReplaySpeed::Factor(factor) => factor,
+                };
+                let due = prev.map_or_else(Instant::now, |(prev_due, prev_timestamp)| {
+                    prev_due
+                        + recorded
+                            .timestamp
+                            .saturating_sub(prev_timestamp)
+                            .div_f64(factor)
+                });
+                let now = Instant::now();
+                if due <= now {
+                    prev = Some((due, recorded.timestamp));
+                    break;
+                }
+                // woken up earlier if the speed is changed
+                self.control
+                    .condvar
+                    .wait_for(&mut state, (due - now).min(IDLE_CHECK_INTERVAL));
+                if self.server.strong_count() == 0 {
+                    break;
+                }
+            }
+            drop(state);
+            let Some(server_inner) = self.server.upgrade() else {
+                trace!(stream_id = self.stream_id, "replay stopped");
+                break;
+            };
+            let _r = server_inner.send_frame(self.stream_id, recorded.frame);
+        }
+        self.control.state.lock().finished = true;
+    }
+}
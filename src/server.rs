@@ -1,27 +1,104 @@
 use std::{
     collections::BTreeMap,
-    io::{Cursor, Read, Write},
-    net::{TcpListener, TcpStream, ToSocketAddrs},
+    io::{self, Cursor, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    path::Path,
     sync::{atomic, Arc},
     thread,
     time::{Duration, Instant},
 };
+#[cfg(unix)]
+use std::{
+    fs,
+    os::unix::{
+        fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+};
 
 use binrw::{BinRead, BinWrite};
 use rtsc::{cell::DataCell, semaphore::Semaphore};
-use tracing::{error, trace};
+use tracing::{error, trace, warn};
 
 const DEFAULT_MAX_CLIENTS: usize = 16;
 
-use crate::{Error, Format, Frame, Greetings, Stream, StreamInfo, StreamSelect, API_VERSION};
+/// Max publish info size (stream info, the name length and a 255-byte name)
+const MAX_PUBLISH_INFO_LEN: usize = 7 + 1 + 255;
+
+/// Max metadata size of published frames
+const MAX_PUBLISHED_METADATA_LEN: usize = 65_536;
+
+/// Extra space of published MJPEG frames over the RGB picture size (JPEG headers and tables)
+const MJPEG_FRAME_MARGIN: usize = 65_536;
+
+#[cfg(any(feature = "test-pattern", feature = "file-source"))]
+use crate::frame_interval;
+use crate::{
+    auth::{self, Nonce, AUTH_HMAC_SHA256, AUTH_NONE, SIGNATURE_SIZE},
+    clip::{Clip, ClipBuffer},
+    multicast,
+    params::{
+        pack, param_status, ControlRequest, ParamEntry, ParamHandler, ParamInternal, ParamList,
+        ParamName, StreamEntry, StreamList, StreamName, PARAM_STATUS_OK,
+    },
+    read_frame_blocks_limited,
+    remote::Remote,
+    replay::{Replay, ReplaySpeed},
+    write_frame_blocks, AccessPolicy, ClientSummary, Error, Event, EventHandler, Format, Frame,
+    Greetings, Param, ParamInfo, ParamKind, ParamValue, RecordingReader, Stream, StreamInfo,
+    StreamSelect, API_VERSION, CLIENT_MSG_ACK, CLIENT_MSG_EVENT, CONTROL_STREAM_ID,
+    MAX_CONTROL_REQUEST_LEN, MAX_EVENT_SIZE, PUBLISH_MSG_FRAME, PUBLISH_MSG_KEEPALIVE,
+    PUBLISH_STREAM_ID, SELECT_STATUS_ACCESS_DENIED, SELECT_STATUS_INVALID_STREAM, SELECT_STATUS_OK,
+    SELECT_STATUS_UNSUPPORTED, SERVER_ERROR_MAGIC, STREAM_FLAG_MULTICAST,
+};
+#[cfg(feature = "file-source")]
+use crate::{file_source, FileSource};
+#[cfg(all(feature = "shm", target_os = "linux"))]
+use crate::{shm::ShmWriter, STREAM_FLAG_SHM};
+#[cfg(feature = "test-pattern")]
+use crate::{test_pattern, TestPattern};
 
 type FrameCell = DataCell<Frame, crate::RawMutex, crate::Condvar>;
 
+struct StreamClient {
+    frame_cell: FrameCell,
+    // None for local clients, which receive all frames
+    max_fps: Option<u8>,
+}
+
 struct StreamInternal {
     format: Format,
     width: u16,
     height: u16,
-    clients: BTreeMap<usize, FrameCell>,
+    clients: BTreeMap<usize, StreamClient>,
+    event_handler: Option<EventHandler>,
+    secret: Option<Vec<u8>>,
+    // the group address and the sender worker client id
+    multicast: Option<(SocketAddr, usize)>,
+    name: Option<String>,
+    // false for remote and published streams while their sources are unavailable
+    online: bool,
+    // the stream is pushed by a publisher
+    published: bool,
+    clip_buffer: Option<ClipBuffer>,
+}
+
+impl StreamInternal {
+    fn new(format: Format, width: u16, height: u16) -> Self {
+        Self {
+            format,
+            clients: <_>::default(),
+            width,
+            height,
+            event_handler: None,
+            secret: None,
+            multicast: None,
+            name: None,
+            online: true,
+            published: false,
+            clip_buffer: None,
+        }
+    }
 }
 
 /// A server instance. The crate creates a default server, however in some circumstances it might
@@ -37,6 +114,11 @@ impl Server {
         Self {
             inner: Arc::new(StreamServerInner {
                 streams: <_>::default(),
+                clients_added: crate::Condvar::new(),
+                params: <_>::default(),
+                secret: <_>::default(),
+                access_policy: <_>::default(),
+                publishing: atomic::AtomicBool::new(false),
                 client_id: atomic::AtomicUsize::new(0),
                 timeout,
                 max_clients: atomic::AtomicUsize::new(DEFAULT_MAX_CLIENTS),
@@ -49,6 +131,24 @@ impl Server {
             .max_clients
             .store(max_clients, atomic::Ordering::Relaxed);
     }
+    /// Require clients to authenticate with the given pre-shared secret. Streams may have own
+    /// secrets, which override the server one.
+    pub fn set_secret(&self, secret: &[u8]) {
+        self.inner.secret.lock().replace(secret.to_vec());
+    }
+    /// Allow remote devices to publish (push) their streams to the server (see
+    /// [`Stream::publish`]). Published streams are exposed to clients as local ones and are
+    /// identified by names, so a re-connected publisher continues its stream. Publishers
+    /// authenticate with the server secret (if set).
+    pub fn allow_publishing(&self, allow: bool) {
+        self.inner
+            .publishing
+            .store(allow, atomic::Ordering::Relaxed);
+    }
+    /// Set the client access policy. By default, all clients are allowed.
+    pub fn set_access_policy(&self, policy: AccessPolicy) {
+        self.inner.access_policy.lock().replace(policy);
+    }
     /// Add a stream to the server
     pub fn add_stream(&self, format: Format, width: u16, height: u16) -> Result<Stream, Error> {
         let stream_id = self.inner.add_stream(format, width, height)?;
@@ -57,24 +157,237 @@ impl Server {
             server_inner: self.inner.clone(),
         })
     }
+    /// Add a stream, fed by a background client of the stream on a remote server. The remote
+    /// server must be available when the stream is added (the stream copies the remote format,
+    /// picture size and name). The client reconnects automatically, the stream is marked offline
+    /// while the remote server is unavailable.
+    pub fn add_remote_stream(
+        &self,
+        addr: impl ToSocketAddrs,
+        remote_stream_id: u16,
+        max_fps: u8,
+        secret: Option<&[u8]>,
+    ) -> Result<Stream, Error> {
+        let remote = Remote {
+            addr: addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or(Error::InvalidAddress)?,
+            stream_id: remote_stream_id,
+            max_fps,
+            secret: secret.map(<[u8]>::to_vec),
+            timeout: self.inner.timeout,
+        };
+        let description = remote.describe()?;
+        let info = description.info;
+        let stream = self.add_stream(info.format, info.width, info.height)?;
+        if let Some(ref name) = description.name {
+            stream.set_name(name)?;
+        }
+        trace!(addr = %remote.addr, remote_stream_id, stream_id = stream.id, "remote stream added");
+        remote.spawn(info, stream.clone());
+        Ok(stream)
+    }
+    /// Add a stream, fed by a background worker, which replays a recording file (the stream
+    /// copies the recorded format, picture size and name). Frames are sent with the recorded
+    /// timing, accelerated or stepped, according to the speed. The replay is controlled with the
+    /// returned object.
+    pub fn add_replay_stream(
+        &self,
+        path: impl AsRef<Path>,
+        speed: ReplaySpeed,
+        looped: bool,
+    ) -> Result<Replay, Error> {
+        let speed = speed.validate()?;
+        let reader = RecordingReader::open(path)?;
+        let info = reader.stream_info();
+        let stream = self.add_stream(info.format, info.width, info.height)?;
+        if let Some(name) = reader.name() {
+            stream.set_name(name)?;
+        }
+        trace!(
+            stream_id = stream.id,
+            frames = reader.frame_count(),
+            "replay stream added"
+        );
+        Replay::spawn(reader, stream, speed, looped)
+    }
+    /// Add a stream, fed by a background worker with synthetic test-pattern frames at the given
+    /// rate, to test clients and networks without a camera. All formats are supported (MJPEG
+    /// frames are encoded with the default quality). Frames are rendered only while the stream
+    /// has clients, the worker stops when the server is dropped. Requires the `test-pattern`
+    /// feature.
+    #[cfg(feature = "test-pattern")]
+    pub fn add_test_pattern_stream(
+        &self,
+        format: Format,
+        width: u16,
+        height: u16,
+        fps: f64,
+        pattern: TestPattern,
+    ) -> Result<Stream, Error> {
+        let interval = frame_interval(fps)?;
+        let stream = self.add_stream(format, width, height)?;
+        trace!(
+            stream_id = stream.id,
+            ?pattern,
+            fps,
+            "test pattern stream added"
+        );
+        test_pattern::spawn(stream.clone(), format, width, height, interval, pattern);
+        Ok(stream)
+    }
+    /// Add a stream, fed by a background worker with images of a directory (PNG/JPEG files, in
+    /// the file name order) or frames of a recording, sent at the given rate. Images and frames
+    /// are converted into the stream format and scaled to the stream picture size, if required.
+    /// When the end of the source is reached, it is restarted if looped. Frames are read only
+    /// while the stream has clients, the worker stops when the server is dropped. Requires the
+    /// `file-source` feature.
+    #[cfg(feature = "file-source")]
+    pub fn add_file_stream(
+        &self,
+        source: FileSource,
+        format: Format,
+        width: u16,
+        height: u16,
+        fps: f64,
+        looped: bool,
+    ) -> Result<Stream, Error> {
+        let interval = frame_interval(fps)?;
+        let reader = file_source::open(&source)?;
+        let stream = self.add_stream(format, width, height)?;
+        if let Some(name) = reader.name() {
+            stream.set_name(name)?;
+        }
+        trace!(stream_id = stream.id, ?source, fps, "file stream added");
+        let info = StreamInfo {
+            id: stream.id,
+            format,
+            width,
+            height,
+        };
+        file_source::spawn(reader, stream.clone(), info, interval, looped);
+        Ok(stream)
+    }
+    /// Add a parameter to the server. Parameters can be listed, read and set by clients. The
+    /// name must be unique and no longer than 255 bytes.
+    pub fn add_param(
+        &self,
+        name: &str,
+        kind: ParamKind,
+        value: ParamValue,
+    ) -> Result<Param, Error> {
+        let id = self.inner.add_param(name, kind, value)?;
+        Ok(Param {
+            id,
+            server_inner: self.inner.clone(),
+        })
+    }
+    /// Get a parameter by name
+    pub fn param(&self, name: &str) -> Option<Param> {
+        let id = self.inner.find_param(name)?;
+        Some(Param {
+            id,
+            server_inner: self.inner.clone(),
+        })
+    }
+    /// Get information about all parameters
+    pub fn params(&self) -> Vec<ParamInfo> {
+        self.inner
+            .params
+            .lock()
+            .iter()
+            .map(|p| p.info.clone())
+            .collect()
+    }
     /// Send frame to the server with stream id
     pub fn send_frame(&self, stream_id: u16, frame: Frame) -> Result<(), Error> {
         self.inner.send_frame(stream_id, frame)
     }
     /// Run the server
     pub fn serve(&self, addr: impl ToSocketAddrs + std::fmt::Debug) -> Result<(), Error> {
+        self.serve_with(addr, |inner, mut socket, peer| {
+            inner.handle_connection(&mut socket, peer)
+        })
+    }
+    /// Run the server, accepting TLS connections only (see [`crate::tls`] for configuration
+    /// helpers)
+    #[cfg(feature = "tls")]
+    pub fn serve_tls(
+        &self,
+        addr: impl ToSocketAddrs + std::fmt::Debug,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<(), Error> {
+        self.serve_with(addr, move |inner, socket, peer| {
+            let conn = rustls::ServerConnection::new(config.clone())?;
+            inner.handle_connection(&mut rustls::StreamOwned::new(conn, socket), peer)
+        })
+    }
+    /// Run the server on a Unix domain socket. The socket file permissions are set to the given
+    /// mode (e.g. 0o660), so access to the server is controlled by the file owner and group. The
+    /// access policy is not applied to Unix socket clients. A stale socket file, left by a
+    /// previous instance, is replaced, the method fails if another server is listening on the
+    /// path.
+    #[cfg(unix)]
+    pub fn serve_unix(&self, path: impl AsRef<Path>, mode: u32) -> Result<(), Error> {
+        let path = path.as_ref();
+        trace!(?path, mode, "starting server");
+        let listener = bind_unix(path, mode)?;
+        self.accept_loop(
+            || {
+                let (socket, _) = listener.accept()?;
+                trace!("new connection");
+                Ok((socket, None))
+            },
+            |inner, mut socket: UnixStream, peer| {
+                socket.set_read_timeout(Some(inner.timeout))?;
+                socket.set_write_timeout(Some(inner.timeout))?;
+                inner.handle_connection(&mut socket, peer)
+            },
+        )
+    }
+    fn serve_with<F>(
+        &self,
+        addr: impl ToSocketAddrs + std::fmt::Debug,
+        handler: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn(&StreamServerInner, TcpStream, Option<IpAddr>) -> Result<(), Error>
+            + Send
+            + Sync
+            + 'static,
+    {
         trace!(?addr, "starting server");
+        let listener = TcpListener::bind(addr)?;
+        self.accept_loop(
+            || {
+                let (socket, addr) = listener.accept()?;
+                trace!(?addr, "new connection");
+                Ok((socket, Some(addr.ip())))
+            },
+            move |inner, socket, peer| {
+                inner.prepare_socket(&socket)?;
+                handler(inner, socket, peer)
+            },
+        )
+    }
+    fn accept_loop<S, A, F>(&self, mut accept: A, handler: F) -> Result<(), Error>
+    where
+        S: Send + 'static,
+        A: FnMut() -> Result<(S, Option<IpAddr>), Error>,
+        F: Fn(&StreamServerInner, S, Option<IpAddr>) -> Result<(), Error> + Send + Sync + 'static,
+    {
         let semaphore: Semaphore<crate::RawMutex, crate::Condvar> =
             Semaphore::new(self.inner.max_clients.load(atomic::Ordering::Relaxed));
-        let listener = TcpListener::bind(addr)?;
-        while let Ok((mut socket, addr)) = listener.accept() {
-            trace!(?addr, "new connection");
+        let handler = Arc::new(handler);
+        while let Ok((socket, peer)) = accept() {
             let inner = self.inner.clone();
+            let handler = handler.clone();
             let permission = semaphore.acquire();
-            trace!(?addr, "handling connection");
+            trace!(?peer, "handling connection");
             thread::spawn(move || {
                 let _permission = permission;
-                let _r = inner.handle_connection(&mut socket);
+                let _r = handler(&inner, socket, peer);
             });
         }
         Ok(())
@@ -83,16 +396,22 @@ impl Server {
 
 pub(crate) struct StreamServerInner {
     streams: crate::Mutex<Vec<StreamInternal>>,
+    // notified when clients are added
+    clients_added: crate::Condvar,
+    params: crate::Mutex<Vec<ParamInternal>>,
+    secret: crate::Mutex<Option<Vec<u8>>>,
+    access_policy: crate::Mutex<Option<AccessPolicy>>,
+    publishing: atomic::AtomicBool,
     client_id: atomic::AtomicUsize,
-    timeout: Duration,
+    pub(crate) timeout: Duration,
     max_clients: atomic::AtomicUsize,
 }
 
 impl Drop for StreamServerInner {
     fn drop(&mut self) {
         for stream in &*self.streams.lock() {
-            for cell in stream.clients.values() {
-                cell.close();
+            for client in stream.clients.values() {
+                client.frame_cell.close();
             }
         }
     }
@@ -102,25 +421,35 @@ impl StreamServerInner {
     fn add_stream(&self, format: Format, width: u16, height: u16) -> Result<u16, Error> {
         trace!(?format, width, height, "adding stream");
         let mut streams = self.streams.lock();
-        if streams.len() >= usize::from(u16::MAX) {
+        if streams.len() >= usize::from(PUBLISH_STREAM_ID) {
             return Err(Error::TooManyStreams);
         }
-        let stream = StreamInternal {
-            format,
-            clients: <_>::default(),
-            width,
-            height,
-        };
-        streams.push(stream);
+        streams.push(StreamInternal::new(format, width, height));
         let stream_id = u16::try_from(streams.len() - 1).unwrap();
         trace!(stream_id, ?format, width, height, "stream added");
         Ok(stream_id)
     }
-    fn add_client(&self, stream_id: u16, client_id: usize) -> Result<FrameCell, Error> {
+    pub(crate) fn next_client_id(&self) -> usize {
+        self.client_id.fetch_add(1, atomic::Ordering::Relaxed)
+    }
+    /// Adds a client of the stream. Local clients (recorders, publishers etc.) have no FPS limit.
+    pub(crate) fn add_client(
+        &self,
+        stream_id: u16,
+        client_id: usize,
+        max_fps: Option<u8>,
+    ) -> Result<FrameCell, Error> {
         trace!(stream_id, client_id, "adding client");
         let frame_cell = FrameCell::default();
         if let Some(stream) = self.streams.lock().get_mut(usize::from(stream_id)) {
-            stream.clients.insert(client_id, frame_cell.clone());
+            stream.clients.insert(
+                client_id,
+                StreamClient {
+                    frame_cell: frame_cell.clone(),
+                    max_fps,
+                },
+            );
+            self.clients_added.notify_all();
             trace!(stream_id, client_id, "client added");
             Ok(frame_cell)
         } else {
@@ -128,12 +457,259 @@ impl StreamServerInner {
             Err(Error::InvalidStream)
         }
     }
-    fn remove_client(&self, stream_id: u16, client_id: usize) {
+    pub(crate) fn remove_client(&self, stream_id: u16, client_id: usize) {
         trace!(stream_id, client_id, "removing client");
         if let Some(stream) = self.streams.lock().get_mut(usize::from(stream_id)) {
-            stream.clients.remove(&client_id);
+            if let Some(client) = stream.clients.remove(&client_id) {
+                client.frame_cell.close();
+            }
+        }
+    }
+    pub(crate) fn client_summary(&self, stream_id: u16) -> ClientSummary {
+        ClientSummary {
+            max_fps: self
+                .streams
+                .lock()
+                .get(usize::from(stream_id))
+                .map_or_else(Vec::new, |stream| {
+                    stream.clients.values().map(|c| c.max_fps).collect()
+                }),
+        }
+    }
+    pub(crate) fn has_clients(&self, stream_id: u16) -> bool {
+        self.streams
+            .lock()
+            .get(usize::from(stream_id))
+            .map_or(false, |stream| !stream.clients.is_empty())
+    }
+    /// Waits until the stream has clients, returns false if timed out
+    pub(crate) fn wait_for_clients(&self, stream_id: u16, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut streams = self.streams.lock();
+        loop {
+            if streams
+                .get(usize::from(stream_id))
+                .map_or(false, |stream| !stream.clients.is_empty())
+            {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            self.clients_added.wait_for(&mut streams, deadline - now);
+        }
+    }
+    pub(crate) fn set_event_handler(&self, stream_id: u16, handler: EventHandler) {
+        if let Some(stream) = self.streams.lock().get_mut(usize::from(stream_id)) {
+            stream.event_handler.replace(handler);
+        }
+    }
+    pub(crate) fn set_stream_secret(&self, stream_id: u16, secret: &[u8]) {
+        if let Some(stream) = self.streams.lock().get_mut(usize::from(stream_id)) {
+            stream.secret.replace(secret.to_vec());
         }
     }
+    pub(crate) fn set_stream_name(&self, stream_id: u16, name: &str) -> Result<(), Error> {
+        if name.len() > usize::from(u8::MAX) {
+            return Err(Error::InvalidStreamName);
+        }
+        let mut streams = self.streams.lock();
+        let stream = streams
+            .get_mut(usize::from(stream_id))
+            .ok_or(Error::InvalidStream)?;
+        stream.name = Some(name.to_owned()).filter(|name| !name.is_empty());
+        Ok(())
+    }
+    pub(crate) fn set_stream_clip_buffer(&self, stream_id: u16, buffer: ClipBuffer) {
+        if let Some(stream) = self.streams.lock().get_mut(usize::from(stream_id)) {
+            stream.clip_buffer.replace(buffer);
+        }
+    }
+    /// Prepares a clip: takes the pre-trigger frames from the stream buffer
+    pub(crate) fn start_clip(
+        &self,
+        stream_id: u16,
+        pre: Duration,
+        post: Duration,
+        label: &str,
+    ) -> Result<Clip, Error> {
+        let info = self.stream_info(stream_id)?;
+        let mut streams = self.streams.lock();
+        let stream = streams
+            .get_mut(usize::from(stream_id))
+            .ok_or(Error::InvalidStream)?;
+        let buffer = stream.clip_buffer.as_mut().ok_or(Error::InvalidClip)?;
+        if pre > buffer.duration() || post > buffer.duration() {
+            return Err(Error::InvalidClip);
+        }
+        let trigger = Instant::now();
+        let start = trigger.checked_sub(pre).unwrap_or(trigger);
+        let end = trigger + post;
+        Ok(Clip {
+            path: Clip::path_for(buffer.dir(), label),
+            info,
+            start,
+            end,
+            pre_frames: buffer.frames_between(start, trigger),
+            clip_id: buffer.start_post_frames(end),
+            name: stream.name.clone(),
+        })
+    }
+    /// Takes post-trigger frames of the clip, collected by the stream buffer
+    pub(crate) fn take_clip_post_frames(
+        &self,
+        stream_id: u16,
+        clip_id: u64,
+    ) -> Vec<(Instant, Frame)> {
+        self.streams
+            .lock()
+            .get_mut(usize::from(stream_id))
+            .and_then(|stream| stream.clip_buffer.as_mut())
+            .map_or_else(Vec::new, |buffer| buffer.take_post_frames(clip_id))
+    }
+    pub(crate) fn set_stream_online(&self, stream_id: u16, online: bool) {
+        if let Some(stream) = self.streams.lock().get_mut(usize::from(stream_id)) {
+            stream.online = online;
+        }
+    }
+    pub(crate) fn stream_online(&self, stream_id: u16) -> bool {
+        self.streams
+            .lock()
+            .get(usize::from(stream_id))
+            .map_or(false, |stream| stream.online)
+    }
+    pub(crate) fn stream_name(&self, stream_id: u16) -> Option<String> {
+        self.streams
+            .lock()
+            .get(usize::from(stream_id))
+            .and_then(|stream| stream.name.clone())
+    }
+    fn stream_list(&self) -> StreamList {
+        StreamList::new(
+            self.streams
+                .lock()
+                .iter()
+                .enumerate()
+                .map(|(id, stream)| StreamEntry {
+                    info: StreamInfo {
+                        id: u16::try_from(id).unwrap(),
+                        format: stream.format,
+                        width: stream.width,
+                        height: stream.height,
+                    },
+                    name: StreamName::new(stream.name.as_deref()),
+                })
+                .collect(),
+        )
+    }
+    pub(crate) fn set_stream_multicast(
+        &self,
+        stream_id: u16,
+        group: SocketAddr,
+    ) -> Result<(), Error> {
+        let socket = multicast::sender(group)?;
+        let client_id = self.client_id.fetch_add(1, atomic::Ordering::Relaxed);
+        let rx = self.add_client(stream_id, client_id, None)?;
+        let prev = self
+            .streams
+            .lock()
+            .get_mut(usize::from(stream_id))
+            .and_then(|stream| stream.multicast.replace((group, client_id)));
+        if let Some((_, prev_client_id)) = prev {
+            self.remove_client(stream_id, prev_client_id);
+        }
+        trace!(stream_id, %group, "multicast enabled");
+        thread::spawn(move || {
+            let mut seq: u32 = 0;
+            for frame in rx {
+                if let Err(error) = multicast::send_frame(&socket, group, stream_id, seq, &frame) {
+                    warn!(stream_id, %group, %error, "unable to send multicast frame");
+                }
+                seq = seq.wrapping_add(1);
+            }
+        });
+        Ok(())
+    }
+    fn stream_multicast(&self, stream_id: u16) -> Option<SocketAddr> {
+        self.streams
+            .lock()
+            .get(usize::from(stream_id))
+            .and_then(|stream| stream.multicast.map(|(group, _)| group))
+    }
+    fn auth_required(&self) -> bool {
+        self.secret.lock().is_some() || self.streams.lock().iter().any(|s| s.secret.is_some())
+    }
+    /// Returns the secret, required for the selected stream (or the control session)
+    fn secret_for(&self, stream_id: u16) -> Result<Option<Vec<u8>>, Error> {
+        if stream_id != CONTROL_STREAM_ID && stream_id != PUBLISH_STREAM_ID {
+            let streams = self.streams.lock();
+            let stream = streams
+                .get(usize::from(stream_id))
+                .ok_or(Error::InvalidStream)?;
+            if stream.secret.is_some() {
+                return Ok(stream.secret.clone());
+            }
+        }
+        Ok(self.secret.lock().clone())
+    }
+    fn handle_event(&self, event: Event) {
+        trace!(
+            stream_id = event.stream_id,
+            client_id = event.client_id,
+            len = event.data.len(),
+            "client event"
+        );
+        let handler = self
+            .streams
+            .lock()
+            .get(usize::from(event.stream_id))
+            .and_then(|stream| stream.event_handler.clone());
+        if let Some(handler) = handler {
+            handler(event);
+        }
+    }
+    fn add_param(&self, name: &str, kind: ParamKind, value: ParamValue) -> Result<usize, Error> {
+        trace!(name, ?kind, ?value, "adding parameter");
+        ParamName::new(name)?;
+        kind.check(&value)?;
+        let mut params = self.params.lock();
+        if params.len() >= usize::from(u16::MAX) || params.iter().any(|p| p.info.name == name) {
+            return Err(Error::InvalidParam);
+        }
+        params.push(ParamInternal {
+            info: ParamInfo {
+                name: name.to_owned(),
+                kind,
+                value,
+            },
+            handler: None,
+        });
+        Ok(params.len() - 1)
+    }
+    fn find_param(&self, name: &str) -> Option<usize> {
+        self.params.lock().iter().position(|p| p.info.name == name)
+    }
+    pub(crate) fn param_info(&self, id: usize) -> ParamInfo {
+        self.params.lock()[id].info.clone()
+    }
+    pub(crate) fn set_param(&self, id: usize, value: ParamValue) -> Result<(), Error> {
+        let handler = {
+            let mut params = self.params.lock();
+            let param = &mut params[id];
+            param.info.kind.check(&value)?;
+            trace!(name = param.info.name, ?value, "setting parameter");
+            param.info.value = value;
+            param.handler.clone()
+        };
+        if let Some(handler) = handler {
+            handler(&value);
+        }
+        Ok(())
+    }
+    pub(crate) fn set_param_handler(&self, id: usize, handler: ParamHandler) {
+        self.params.lock()[id].handler.replace(handler);
+    }
     fn stream_count(&self) -> usize {
         self.streams.lock().len()
     }
@@ -150,9 +726,16 @@ impl StreamServerInner {
             return Err(Error::FrameDataTooLarge);
         }
         let clients = {
-            let streams = self.streams.lock();
-            if let Some(stream) = streams.get(usize::from(stream_id)) {
-                stream.clients.values().cloned().collect::<Vec<FrameCell>>()
+            let mut streams = self.streams.lock();
+            if let Some(stream) = streams.get_mut(usize::from(stream_id)) {
+                if let Some(ref mut clip_buffer) = stream.clip_buffer {
+                    clip_buffer.push(&frame);
+                }
+                stream
+                    .clients
+                    .values()
+                    .map(|client| client.frame_cell.clone())
+                    .collect::<Vec<FrameCell>>()
             } else {
                 return Err(Error::InvalidStream);
             }
@@ -171,32 +754,141 @@ impl StreamServerInner {
         g.write(&mut writer).unwrap();
         writer.into_inner()
     }
-    fn stream_info_packed(&self, stream_id: u16) -> Result<Vec<u8>, Error> {
+    pub(crate) fn stream_info(&self, stream_id: u16) -> Result<StreamInfo, Error> {
         let streams = self.streams.lock();
         let Some(stream) = streams.get(usize::from(stream_id)) else {
             return Err(Error::InvalidStream);
         };
-        let si = StreamInfo {
+        Ok(StreamInfo {
             id: stream_id,
             format: stream.format,
             width: stream.width,
             height: stream.height,
-        };
+        })
+    }
+    fn stream_info_packed(&self, stream_id: u16) -> Result<Vec<u8>, Error> {
+        let si = self.stream_info(stream_id)?;
         let mut writer = Cursor::new(Vec::new());
         si.write(&mut writer).unwrap();
         Ok(writer.into_inner())
     }
-    fn handle_connection(&self, socket: &mut TcpStream) -> Result<(), Error> {
+    fn prepare_socket(&self, socket: &TcpStream) -> Result<(), Error> {
         socket.set_nodelay(true)?;
         socket.set_read_timeout(Some(self.timeout))?;
         socket.set_write_timeout(Some(self.timeout))?;
-        socket.write_all(&self.greetings())?;
-        let stream_select_buf = &mut [0u8; 3];
+        Ok(())
+    }
+    fn handle_connection(
+        &self,
+        socket: &mut impl Connection,
+        peer: Option<IpAddr>,
+    ) -> Result<(), Error> {
+        // the access policy is applied to IP clients only
+        let policy = peer.and_then(|ip| Some((ip, self.access_policy.lock().clone()?)));
+        if let Some((peer, ref policy)) = policy {
+            if !policy.allows_address(peer) {
+                warn!(%peer, "client address rejected by the access policy");
+                socket.write_all(&[SERVER_ERROR_MAGIC, SELECT_STATUS_ACCESS_DENIED, 0, 0])?;
+                return Err(Error::AccessDenied);
+            }
+        }
+        let mut hello = self.greetings();
+        let nonce = if self.auth_required() {
+            let nonce = auth::nonce()?;
+            hello.push(AUTH_HMAC_SHA256);
+            hello.extend(nonce);
+            Some(nonce)
+        } else {
+            hello.push(AUTH_NONE);
+            None
+        };
+        socket.write_all(&hello)?;
+        let stream_select_buf = &mut [0u8; 4];
         socket.read_exact(stream_select_buf)?;
         let stream_select = StreamSelect::read(&mut Cursor::new(stream_select_buf)).unwrap();
-        let stram_info_packed = self.stream_info_packed(stream_select.stream_id)?;
-        socket.write_all(&stram_info_packed)?;
+        let mut signature = [0u8; SIGNATURE_SIZE];
+        if nonce.is_some() {
+            socket.read_exact(&mut signature)?;
+        }
+        if let Some((peer, ref policy)) = policy {
+            let allowed = match stream_select.stream_id {
+                CONTROL_STREAM_ID => policy.allows_control(peer),
+                PUBLISH_STREAM_ID => policy.allows_publishing(peer),
+                stream_id => policy.allows_stream(peer, stream_id),
+            };
+            if !allowed {
+                warn!(
+                    %peer,
+                    stream_id = stream_select.stream_id,
+                    "stream access rejected by the access policy"
+                );
+                socket.write_all(&[SELECT_STATUS_ACCESS_DENIED])?;
+                return Err(Error::AccessDenied);
+            }
+        }
+        if let Err(e) = self.authenticate(stream_select.stream_id, nonce.as_ref(), &signature) {
+            let status = if matches!(e, Error::InvalidStream) {
+                error!(
+                    stream_id = stream_select.stream_id,
+                    "client requested invalid stream"
+                );
+                SELECT_STATUS_INVALID_STREAM
+            } else {
+                warn!(
+                    ?peer,
+                    stream_id = stream_select.stream_id,
+                    "client authentication failed"
+                );
+                SELECT_STATUS_ACCESS_DENIED
+            };
+            socket.write_all(&[status])?;
+            return Err(e);
+        }
+        let multicast = if stream_select.flags & STREAM_FLAG_MULTICAST == 0 {
+            None
+        } else {
+            self.stream_multicast(stream_select.stream_id)
+        };
+        if stream_select.flags & !(socket.stream_flags() | STREAM_FLAG_MULTICAST) != 0
+            || (stream_select.flags & STREAM_FLAG_MULTICAST != 0
+                && (multicast.is_none() || stream_select.flags != STREAM_FLAG_MULTICAST))
+        {
+            warn!(
+                ?peer,
+                flags = stream_select.flags,
+                "client requested unsupported transport"
+            );
+            socket.write_all(&[SELECT_STATUS_UNSUPPORTED])?;
+            return Err(Error::UnsupportedTransport);
+        }
+        if stream_select.stream_id == CONTROL_STREAM_ID {
+            socket.write_all(&[SELECT_STATUS_OK])?;
+            return self.handle_control(socket, policy.as_ref());
+        }
+        if stream_select.stream_id == PUBLISH_STREAM_ID {
+            if !self.publishing.load(atomic::Ordering::Relaxed) {
+                warn!(?peer, "publishing is not allowed");
+                socket.write_all(&[SELECT_STATUS_ACCESS_DENIED])?;
+                return Err(Error::AccessDenied);
+            }
+            socket.write_all(&[SELECT_STATUS_OK])?;
+            return self.handle_publisher(socket);
+        }
+        let mut stream_info_packed = vec![SELECT_STATUS_OK];
+        stream_info_packed.extend(self.stream_info_packed(stream_select.stream_id)?);
+        if let Some(group) = multicast {
+            stream_info_packed.extend(multicast::pack_group(group));
+        }
+        socket.write_all(&stream_info_packed)?;
         let client_id = self.client_id.fetch_add(1, atomic::Ordering::Relaxed);
+        if multicast.is_some() {
+            trace!(
+                stream_id = stream_select.stream_id,
+                client_id,
+                "multicast connection established"
+            );
+            return self.handle_multicast_client(socket, stream_select.stream_id, client_id);
+        }
         trace!(
             stream_id = stream_select.stream_id,
             max_fps = stream_select.max_fps,
@@ -205,7 +897,13 @@ impl StreamServerInner {
         );
         let min_time_between_frames: Duration =
             Duration::from_secs_f64(1.0 / f64::from(stream_select.max_fps));
-        let rx = self.add_client(stream_select.stream_id, client_id)?;
+        let rx = self.add_client(
+            stream_select.stream_id,
+            client_id,
+            Some(stream_select.max_fps),
+        )?;
+        #[cfg(all(feature = "shm", target_os = "linux"))]
+        let mut shm = (stream_select.flags & STREAM_FLAG_SHM != 0).then(ShmWriter::default);
         let mut last_frame = None;
         for frame in rx {
             let now = Instant::now();
@@ -216,27 +914,338 @@ impl StreamServerInner {
                 }
             }
             last_frame.replace(now);
-            if Self::write_frame(socket, frame).is_err() {
+            #[cfg(all(feature = "shm", target_os = "linux"))]
+            if let Some(ref mut shm) = shm {
+                if self
+                    .write_frame_shm(socket, shm, &frame, stream_select.stream_id, client_id)
+                    .is_err()
+                {
+                    self.remove_client(stream_select.stream_id, client_id);
+                    break;
+                }
+                continue;
+            }
+            if self
+                .write_frame(socket, frame, stream_select.stream_id, client_id)
+                .is_err()
+            {
                 self.remove_client(stream_select.stream_id, client_id);
                 break;
             }
         }
         Ok(())
     }
-    fn write_frame(socket: &mut TcpStream, frame: Frame) -> Result<(), Error> {
-        let metadata_len = u32::try_from(frame.metadata.as_ref().map_or(0, |v| v.len())).unwrap();
-        socket.write_all(&metadata_len.to_le_bytes())?;
-        if let Some(ref metadata) = frame.metadata {
-            socket.write_all(metadata)?;
+    fn authenticate(
+        &self,
+        stream_id: u16,
+        nonce: Option<&Nonce>,
+        signature: &[u8; SIGNATURE_SIZE],
+    ) -> Result<(), Error> {
+        let Some(secret) = self.secret_for(stream_id)? else {
+            return Ok(());
+        };
+        let Some(nonce) = nonce else {
+            // the secret has been set after the challenge was sent
+            return Err(Error::AccessDenied);
+        };
+        if auth::verify(&secret, nonce, signature) {
+            Ok(())
+        } else {
+            Err(Error::AccessDenied)
+        }
+    }
+    /// The stream list contains the streams, allowed for the client by the access policy, only
+    fn handle_control(
+        &self,
+        socket: &mut (impl Read + Write),
+        policy: Option<&(IpAddr, AccessPolicy)>,
+    ) -> Result<(), Error> {
+        trace!("control session established");
+        loop {
+            let mut len_buf = [0u8; 4];
+            socket.read_exact(&mut len_buf)?;
+            let len =
+                usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::InvalidParam)?;
+            if len > MAX_CONTROL_REQUEST_LEN {
+                warn!(len, "control request too large");
+                return Err(Error::InvalidParam);
+            }
+            let mut request_buf = vec![0u8; len];
+            socket.read_exact(&mut request_buf)?;
+            let request = ControlRequest::read(&mut Cursor::new(request_buf))?;
+            trace!(?request, "control request");
+            let mut response = vec![PARAM_STATUS_OK];
+            match request {
+                ControlRequest::ListParams => {
+                    let params = self
+                        .params
+                        .lock()
+                        .iter()
+                        .map(|p| ParamEntry {
+                            name: ParamName::new(&p.info.name).unwrap(),
+                            kind: p.info.kind,
+                            value: p.info.value,
+                        })
+                        .collect();
+                    response.extend(pack(&ParamList::new(params))?);
+                }
+                ControlRequest::ListStreams => {
+                    let mut list = self.stream_list();
+                    if let Some((peer, policy)) = policy {
+                        list.streams
+                            .retain(|entry| policy.allows_stream(*peer, entry.info.id));
+                    }
+                    response.extend(pack(&list)?);
+                }
+                ControlRequest::GetParam(name) => match self.find_param(&name.name) {
+                    Some(id) => response.extend(pack(&self.param_info(id).value)?),
+                    None => response[0] = param_status(&Error::ParamNotFound),
+                },
+                ControlRequest::SetParam(name, value) => {
+                    if let Err(e) = self
+                        .find_param(&name.name)
+                        .ok_or(Error::ParamNotFound)
+                        .and_then(|id| self.set_param(id, value))
+                    {
+                        response[0] = param_status(&e);
+                    }
+                }
+            }
+            let len = u32::try_from(response.len()).unwrap();
+            socket.write_all(&len.to_le_bytes())?;
+            socket.write_all(&response)?;
+        }
+    }
+    fn write_frame(
+        &self,
+        socket: &mut (impl Read + Write),
+        frame: Frame,
+        stream_id: u16,
+        client_id: usize,
+    ) -> Result<(), Error> {
+        write_frame_blocks(socket, &frame)?;
+        self.read_client_messages(socket, stream_id, client_id)
+    }
+    /// Writes the frame into the shared memory ring and sends its descriptor
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    fn write_frame_shm(
+        &self,
+        socket: &mut impl Connection,
+        shm: &mut ShmWriter,
+        frame: &Frame,
+        stream_id: u16,
+        client_id: usize,
+    ) -> Result<(), Error> {
+        shm.write_frame(
+            socket.unix_socket().ok_or(Error::UnsupportedTransport)?,
+            frame,
+        )?;
+        self.read_client_messages(socket, stream_id, client_id)
+    }
+    fn handle_publisher(&self, socket: &mut impl Connection) -> Result<(), Error> {
+        let mut len_buf = [0u8; 4];
+        socket.read_exact(&mut len_buf)?;
+        let len = usize::try_from(u32::from_le_bytes(len_buf)).map_err(|_| Error::InvalidStream)?;
+        if len > MAX_PUBLISH_INFO_LEN {
+            warn!(len, "publish info too large");
+            return Err(Error::InvalidStream);
         }
-        let data_len = u32::try_from(frame.data.len()).unwrap();
-        socket.write_all(&data_len.to_le_bytes())?;
-        socket.write_all(&frame.data)?;
-        let mut buf = [0u8; 1];
+        let mut buf = vec![0u8; len];
         socket.read_exact(&mut buf)?;
-        if buf[0] != 0 {
-            return Err(Error::NotReady);
+        let entry = StreamEntry::read(&mut Cursor::new(buf))?;
+        let stream_id = match self.published_stream(&entry.info, &entry.name.name) {
+            Ok(stream_id) => stream_id,
+            Err(e) => {
+                error!(name = entry.name.name, error = %e, "publisher rejected");
+                socket.write_all(&[SELECT_STATUS_INVALID_STREAM, 0, 0])?;
+                return Err(e);
+            }
+        };
+        let mut result = vec![SELECT_STATUS_OK];
+        result.extend(stream_id.to_le_bytes());
+        socket.write_all(&result)?;
+        trace!(stream_id, name = entry.name.name, "publisher connected");
+        let result = self.receive_published_frames(socket, stream_id, &entry.info);
+        self.set_stream_online(stream_id, false);
+        trace!(stream_id, "publisher disconnected");
+        result
+    }
+    /// Finds the stream, published before with the same name, or creates a new one
+    fn published_stream(&self, info: &StreamInfo, name: &str) -> Result<u16, Error> {
+        if name.is_empty() {
+            return Err(Error::InvalidStreamName);
         }
-        Ok(())
+        let mut streams = self.streams.lock();
+        if let Some((stream_id, stream)) = streams
+            .iter_mut()
+            .enumerate()
+            .find(|(_, s)| s.name.as_deref() == Some(name))
+        {
+            // a local stream or a stream of an active publisher can not be taken
+            if !stream.published
+                || stream.online
+                || stream.format != info.format
+                || stream.width != info.width
+                || stream.height != info.height
+            {
+                return Err(Error::InvalidStream);
+            }
+            stream.online = true;
+            return Ok(u16::try_from(stream_id).unwrap());
+        }
+        if streams.len() >= usize::from(PUBLISH_STREAM_ID) {
+            return Err(Error::TooManyStreams);
+        }
+        let mut stream = StreamInternal::new(info.format, info.width, info.height);
+        stream.name = Some(name.to_owned());
+        stream.published = true;
+        streams.push(stream);
+        Ok(u16::try_from(streams.len() - 1).unwrap())
+    }
+    fn receive_published_frames(
+        &self,
+        socket: &mut impl Connection,
+        stream_id: u16,
+        info: &StreamInfo,
+    ) -> Result<(), Error> {
+        // raw frames can not be larger than the announced picture, MJPEG ones are limited by the
+        // size of the RGB picture
+        let pixels = usize::from(info.width) * usize::from(info.height);
+        let max_data_len = info
+            .format
+            .bytes_per_pixel()
+            .map_or(pixels * 3 + MJPEG_FRAME_MARGIN, |bpp| pixels * bpp);
+        loop {
+            let mut buf = [0u8; 1];
+            socket.read_exact(&mut buf)?;
+            match buf[0] {
+                PUBLISH_MSG_KEEPALIVE => {}
+                PUBLISH_MSG_FRAME => {
+                    let frame =
+                        read_frame_blocks_limited(socket, MAX_PUBLISHED_METADATA_LEN, max_data_len)
+                            .inspect_err(|error| {
+                                warn!(stream_id, %error, "invalid published frame");
+                            })?;
+                    self.send_frame(stream_id, frame)?;
+                    socket.write_all(&[CLIENT_MSG_ACK])?;
+                }
+                _ => return Err(Error::NotReady),
+            }
+        }
+    }
+    /// Frames are delivered to multicast clients by the stream sender worker, the connection is
+    /// kept open to process events (acknowledgments are ignored)
+    fn handle_multicast_client(
+        &self,
+        socket: &mut impl Connection,
+        stream_id: u16,
+        client_id: usize,
+    ) -> Result<(), Error> {
+        loop {
+            match self.read_client_messages(socket, stream_id, client_id) {
+                Ok(()) => {}
+                Err(Error::Io(e))
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    /// Reads client messages until the frame acknowledgment is received
+    fn read_client_messages(
+        &self,
+        socket: &mut (impl Read + Write),
+        stream_id: u16,
+        client_id: usize,
+    ) -> Result<(), Error> {
+        loop {
+            let mut buf = [0u8; 1];
+            socket.read_exact(&mut buf)?;
+            match buf[0] {
+                CLIENT_MSG_ACK => return Ok(()),
+                CLIENT_MSG_EVENT => {
+                    let mut len_buf = [0u8; 4];
+                    socket.read_exact(&mut len_buf)?;
+                    let len = usize::try_from(u32::from_le_bytes(len_buf))
+                        .map_err(|_| Error::EventTooLarge)?;
+                    if len > MAX_EVENT_SIZE {
+                        warn!(stream_id, client_id, len, "client event too large");
+                        return Err(Error::EventTooLarge);
+                    }
+                    let mut data = vec![0u8; len];
+                    socket.read_exact(&mut data)?;
+                    self.handle_event(Event {
+                        stream_id,
+                        client_id,
+                        data,
+                    });
+                }
+                _ => return Err(Error::NotReady),
+            }
+        }
+    }
+}
+
+/// Server connection transport
+trait Connection: Read + Write {
+    /// Stream select flags, supported by the transport
+    fn stream_flags(&self) -> u8 {
+        0
+    }
+    /// The underlying Unix socket of local connections
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    fn unix_socket(&self) -> Option<&UnixStream> {
+        None
+    }
+}
+
+impl Connection for TcpStream {}
+
+#[cfg(feature = "tls")]
+impl Connection for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {}
+
+/// Binds a Unix socket. The socket is created in a private directory and moved to the path after
+/// its permissions are set, so it is never accessible with wider permissions than requested.
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener, Error> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() || UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use", path.display()),
+            )
+            .into());
+        }
+    }
+    let file_name = path.file_name().ok_or(Error::InvalidAddress)?;
+    let dir = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let tmp_path = dir.join("socket");
+    let result = UnixListener::bind(&tmp_path).and_then(|listener| {
+        fs::set_permissions(&tmp_path, fs::Permissions::from_mode(mode))?;
+        // a stale socket file is replaced
+        fs::rename(&tmp_path, path)?;
+        Ok(listener)
+    });
+    let _r = fs::remove_file(&tmp_path);
+    let _r = fs::remove_dir(&dir);
+    Ok(result?)
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    fn stream_flags(&self) -> u8 {
+        STREAM_FLAG_SHM
+    }
+    #[cfg(all(feature = "shm", target_os = "linux"))]
+    fn unix_socket(&self) -> Option<&UnixStream> {
+        Some(self)
     }
 }
@@ -0,0 +1,219 @@
+//! Shared-memory frame transport for local clients (Linux only). The server places frame data
+//! into a memfd-backed ring, the ring file descriptor is passed to the client over the Unix
+//! socket, which carries small frame descriptors only.
+use std::{
+    fs::File,
+    io::{self, IoSlice, IoSliceMut, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+};
+
+use memmap2::{Mmap, MmapMut};
+use nix::{
+    errno::Errno,
+    sys::{
+        memfd::{memfd_create, MemFdCreateFlag},
+        socket::{recvmsg, sendmsg, ControlMessage, ControlMessageOwned, MsgFlags},
+    },
+};
+
+use crate::{Error, Frame};
+
+/// A new ring file descriptor is attached to the frame descriptor
+const SHM_FLAG_NEW_RING: u8 = 0x01;
+
+const DESCRIPTOR_SIZE: usize = 17;
+
+/// Frames are written into the ring slots in turn, so the server can write the next frame while
+/// the client is still processing the previous one
+const RING_SLOTS: usize = 2;
+
+const MIN_SLOT_SIZE: usize = 4096;
+
+/// A frame, received over shared memory. The data is borrowed from the shared memory ring and is
+/// valid until the next frame is requested.
+pub struct ShmFrame<'a> {
+    /// An optional metadata (encoded in a way, known to remotes)
+    pub metadata: Option<&'a [u8]>,
+    /// The frame data (encoded/compressed into the stream format)
+    pub data: &'a [u8],
+}
+
+impl ShmFrame<'_> {
+    /// Copies the frame data into an owned frame
+    pub fn to_frame(&self) -> Frame {
+        Frame {
+            metadata: self.metadata.map(|v| v.to_vec().into()),
+            data: self.data.to_vec().into(),
+        }
+    }
+}
+
+struct Ring {
+    file: File,
+    map: MmapMut,
+    slot_size: usize,
+}
+
+impl Ring {
+    fn create(min_slot_size: usize) -> Result<Self, Error> {
+        let slot_size = min_slot_size.max(MIN_SLOT_SIZE).next_power_of_two();
+        let fd = memfd_create(c"rvideo", MemFdCreateFlag::MFD_CLOEXEC).map_err(io::Error::from)?;
+        let file = File::from(fd);
+        let size = slot_size
+            .checked_mul(RING_SLOTS)
+            .and_then(|size| u64::try_from(size).ok())
+            .ok_or(Error::FrameDataTooLarge)?;
+        file.set_len(size)?;
+        // SAFETY: the memfd is owned by the ring and is never truncated while mapped
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(Self {
+            file,
+            map,
+            slot_size,
+        })
+    }
+}
+
+/// Server side of a shared memory connection
+#[derive(Default)]
+pub(crate) struct ShmWriter {
+    ring: Option<Ring>,
+    next_slot: usize,
+}
+
+impl ShmWriter {
+    /// Copies the frame data into the next ring slot and sends the frame descriptor. If the frame
+    /// does not fit the slot, a new (larger) ring is created and sent to the client.
+    pub(crate) fn write_frame(
+        &mut self,
+        mut socket: &UnixStream,
+        frame: &Frame,
+    ) -> Result<(), Error> {
+        let metadata = frame.metadata.as_ref().map_or(&[][..], |v| v.as_slice());
+        let metadata_len =
+            u32::try_from(metadata.len()).map_err(|_| Error::FrameMetaDataTooLarge)?;
+        let data_len = u32::try_from(frame.data.len()).map_err(|_| Error::FrameDataTooLarge)?;
+        let mut flags = 0;
+        if self
+            .ring
+            .as_ref()
+            .map_or(true, |ring| ring.slot_size < frame.data.len())
+        {
+            self.ring.replace(Ring::create(frame.data.len())?);
+            self.next_slot = 0;
+            flags |= SHM_FLAG_NEW_RING;
+        }
+        let ring = self.ring.as_mut().unwrap();
+        let offset = self.next_slot * ring.slot_size;
+        ring.map[offset..offset + frame.data.len()].copy_from_slice(&frame.data);
+        self.next_slot = (self.next_slot + 1) % RING_SLOTS;
+        let mut descriptor = Vec::with_capacity(DESCRIPTOR_SIZE + metadata.len());
+        descriptor.push(flags);
+        descriptor.extend((offset as u64).to_le_bytes());
+        descriptor.extend(data_len.to_le_bytes());
+        descriptor.extend(metadata_len.to_le_bytes());
+        descriptor.extend(metadata);
+        let fds = [ring.file.as_raw_fd()];
+        let cmsgs: &[ControlMessage] = if flags & SHM_FLAG_NEW_RING == 0 {
+            &[]
+        } else {
+            &[ControlMessage::ScmRights(&fds)]
+        };
+        let sent = loop {
+            match sendmsg::<()>(
+                socket.as_raw_fd(),
+                &[IoSlice::new(&descriptor)],
+                cmsgs,
+                MsgFlags::MSG_NOSIGNAL,
+                None,
+            ) {
+                Err(Errno::EINTR) => continue,
+                res => break res.map_err(io::Error::from)?,
+            }
+        };
+        // the ring descriptor is attached to the first sent byte, the rest is sent as usual
+        socket.write_all(&descriptor[sent..])?;
+        Ok(())
+    }
+}
+
+/// Client side of a shared memory connection
+#[derive(Default)]
+pub(crate) struct ShmReader {
+    map: Option<Mmap>,
+    metadata: Vec<u8>,
+}
+
+impl ShmReader {
+    /// Reads a frame descriptor and maps a new ring if one is attached
+    pub(crate) fn read_frame(&mut self, mut socket: &UnixStream) -> Result<ShmFrame<'_>, Error> {
+        let mut descriptor = [0u8; DESCRIPTOR_SIZE];
+        let mut cmsg_buf = nix::cmsg_space!([RawFd; 1]);
+        let (received, fd) = loop {
+            let mut iov = [IoSliceMut::new(&mut descriptor)];
+            match recvmsg::<()>(
+                socket.as_raw_fd(),
+                &mut iov,
+                Some(&mut cmsg_buf),
+                MsgFlags::MSG_CMSG_CLOEXEC,
+            ) {
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(io::Error::from(e).into()),
+                Ok(msg) => {
+                    let mut fd = None;
+                    for cmsg in msg.cmsgs().map_err(io::Error::from)? {
+                        if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                            for raw_fd in fds {
+                                // SAFETY: the descriptor has been just received and is not owned
+                                // by anyone else, extra ones (if any) are closed on drop
+                                let owned = unsafe { OwnedFd::from_raw_fd(raw_fd) };
+                                fd.get_or_insert(owned);
+                            }
+                        }
+                    }
+                    break (msg.bytes, fd);
+                }
+            }
+        };
+        if received == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        socket.read_exact(&mut descriptor[received..])?;
+        let flags = descriptor[0];
+        let offset = usize::try_from(u64::from_le_bytes(descriptor[1..9].try_into().unwrap()))
+            .map_err(|_| invalid_data("invalid frame offset"))?;
+        let data_len = usize::try_from(u32::from_le_bytes(descriptor[9..13].try_into().unwrap()))
+            .map_err(|_| Error::FrameDataTooLarge)?;
+        let metadata_len =
+            usize::try_from(u32::from_le_bytes(descriptor[13..17].try_into().unwrap()))
+                .map_err(|_| Error::FrameMetaDataTooLarge)?;
+        if flags & SHM_FLAG_NEW_RING != 0 {
+            let file = File::from(fd.ok_or_else(|| invalid_data("shared memory ring missing"))?);
+            // SAFETY: the ring is written by the server into the slots, which are not read by
+            // the client at the moment
+            self.map.replace(unsafe { Mmap::map(&file)? });
+        }
+        self.metadata.resize(metadata_len, 0);
+        socket.read_exact(&mut self.metadata)?;
+        let map = self.map.as_ref().ok_or(Error::NotReady)?;
+        let data = offset
+            .checked_add(data_len)
+            .and_then(|end| map.get(offset..end))
+            .ok_or_else(|| invalid_data("frame out of the shared memory ring"))?;
+        Ok(ShmFrame {
+            metadata: if metadata_len > 0 {
+                Some(&self.metadata)
+            } else {
+                None
+            },
+            data,
+        })
+    }
+}
+
+fn invalid_data(msg: &str) -> Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg).into()
+}
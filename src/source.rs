@@ -0,0 +1,64 @@
+//! Paced background workers, which feed local streams (test patterns, file sources)
+use std::{
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::trace;
+
+use crate::{Frame, Stream};
+
+/// The worker re-checks if the stream is still alive while waiting for clients
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The next action of a source
+pub(crate) enum Next {
+    /// Send the frame
+    Frame(Frame),
+    /// Skip the frame (e.g. failed), the pace is kept
+    #[cfg_attr(not(feature = "file-source"), allow(dead_code))]
+    Skip,
+    /// Stop the worker
+    Stop,
+}
+
+/// Starts a worker, which sends frames of the source at the given interval. Frames are produced
+/// only while the stream has clients. The worker keeps a weak reference to the server and stops
+/// when the server is dropped.
+pub(crate) fn spawn(
+    stream: Stream,
+    interval: Duration,
+    mut source: impl FnMut() -> Next + Send + 'static,
+) {
+    let stream_id = stream.id;
+    let server = Arc::downgrade(&stream.server_inner);
+    drop(stream);
+    thread::spawn(move || {
+        let mut next = Instant::now();
+        while let Some(server_inner) = server.upgrade() {
+            if !server_inner.has_clients(stream_id) {
+                server_inner.wait_for_clients(stream_id, IDLE_CHECK_INTERVAL);
+                next = Instant::now();
+                continue;
+            }
+            match source() {
+                Next::Frame(frame) => {
+                    let _r = server_inner.send_frame(stream_id, frame);
+                }
+                Next::Skip => {}
+                Next::Stop => break,
+            }
+            drop(server_inner);
+            next += interval;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                // do not send bursts after delays
+                next = now;
+            }
+        }
+        trace!(stream_id, "source worker stopped");
+    });
+}
@@ -0,0 +1,326 @@
+//! Synthetic test-pattern streams, to test clients and networks without a camera (requires the
+//! `test-pattern` feature)
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use jpeg_encoder::{ColorType, Encoder};
+use tracing::warn;
+
+use crate::{
+    source::{self, Next},
+    Error, Format, Stream,
+};
+
+const JPEG_QUALITY: u8 = 85;
+
+/// 75% color bars: white, yellow, cyan, green, magenta, red, blue
+const BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// Test pattern picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPicture {
+    /// Color bars above a grayscale ramp with a marker, moving with each frame
+    ColorBars,
+    /// A color gradient, moving with each frame
+    Gradient,
+}
+
+/// Test pattern configuration for [`crate::Server::add_test_pattern_stream`]
+#[derive(Debug, Clone)]
+pub struct TestPattern {
+    picture: TestPicture,
+    frame_counter: bool,
+    timestamp: bool,
+}
+
+impl Default for TestPattern {
+    fn default() -> Self {
+        Self::new(TestPicture::ColorBars)
+    }
+}
+
+impl TestPattern {
+    /// Create a new test pattern. The frame counter and timestamp overlays are enabled by default.
+    pub fn new(picture: TestPicture) -> Self {
+        Self {
+            picture,
+            frame_counter: true,
+            timestamp: true,
+        }
+    }
+    /// Draw the frame number in the top-left corner
+    pub fn frame_counter(mut self, enabled: bool) -> Self {
+        self.frame_counter = enabled;
+        self
+    }
+    /// Draw the current UTC date and time in the bottom-left corner
+    pub fn timestamp(mut self, enabled: bool) -> Self {
+        self.timestamp = enabled;
+        self
+    }
+}
+
+/// Draws pictures into an RGB canvas and converts them into the stream format
+struct Renderer {
+    pattern: TestPattern,
+    format: Format,
+    width: usize,
+    height: usize,
+    canvas: Vec<u8>,
+}
+
+impl Renderer {
+    fn new(pattern: TestPattern, format: Format, width: u16, height: u16) -> Self {
+        let (width, height) = (usize::from(width), usize::from(height));
+        Self {
+            pattern,
+            format,
+            width,
+            height,
+            canvas: vec![0; width * height * 3],
+        }
+    }
+    fn render(&mut self, frame_number: u64) -> Result<Vec<u8>, Error> {
+        match self.pattern.picture {
+            TestPicture::ColorBars => self.draw_color_bars(frame_number),
+            TestPicture::Gradient => self.draw_gradient(frame_number),
+        }
+        let scale = (self.width.min(self.height) / 160).max(1);
+        if self.pattern.frame_counter {
+            self.draw_text(&frame_number.to_string(), scale * 2, scale * 2, scale);
+        }
+        if self.pattern.timestamp {
+            let y = self.height.saturating_sub((GLYPH_HEIGHT + 2) * scale);
+            self.draw_text(&utc_timestamp(SystemTime::now()), scale * 2, y, scale);
+        }
+        self.encode()
+    }
+    fn put(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let pos = (y * self.width + x) * 3;
+            self.canvas[pos..pos + 3].copy_from_slice(&color);
+        }
+    }
+    fn draw_color_bars(&mut self, frame_number: u64) {
+        let bars_height = self.height * 3 / 4;
+        let marker_width = (self.width / 32).max(1);
+        let marker_x = (frame_number as usize * marker_width / 4) % self.width.max(1);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = if y < bars_height {
+                    BARS[x * BARS.len() / self.width]
+                } else if x >= marker_x && x < marker_x + marker_width {
+                    [255, 0, 0]
+                } else {
+                    let v = (x * 255 / (self.width - 1).max(1)) as u8;
+                    [v, v, v]
+                };
+                self.put(x, y, color);
+            }
+        }
+    }
+    fn draw_gradient(&mut self, frame_number: u64) {
+        // triangle wave, so the gradient has no edges
+        fn wave(v: usize) -> u8 {
+            let v = v % 512;
+            (if v > 255 { 511 - v } else { v }) as u8
+        }
+        let shift = (frame_number % 512) as usize * 4;
+        let (w, h) = (self.width.max(1), self.height.max(1));
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let color = [
+                    wave(x * 511 / w + shift),
+                    wave(y * 511 / h + shift / 2),
+                    wave((x * 255 / w + y * 255 / h) + 511 - shift % 512),
+                ];
+                self.put(x, y, color);
+            }
+        }
+    }
+    /// Draws white text on a black box, clipped by the picture
+    fn draw_text(&mut self, text: &str, x: usize, y: usize, scale: usize) {
+        let advance = (GLYPH_WIDTH + 1) * scale;
+        for by in y.saturating_sub(scale)..y + (GLYPH_HEIGHT + 1) * scale {
+            for bx in x.saturating_sub(scale)..x + text.len() * advance {
+                self.put(bx, by, [0, 0, 0]);
+            }
+        }
+        for (i, c) in text.chars().enumerate() {
+            let glyph = glyph(c);
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            self.put(
+                                x + i * advance + col * scale + dx,
+                                y + row * scale + dy,
+                                [255, 255, 255],
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+    fn encode(&self) -> Result<Vec<u8>, Error> {
+        let pixels = self.canvas.chunks_exact(3);
+        let luma = |p: &[u8]| -> u8 {
+            ((299 * u32::from(p[0]) + 587 * u32::from(p[1]) + 114 * u32::from(p[2])) / 1000) as u8
+        };
+        // 16-bit samples are little-endian
+        let wide = |v: u8| (u16::from(v) * 257).to_le_bytes();
+        Ok(match self.format {
+            Format::Luma8 => pixels.map(luma).collect(),
+            Format::Luma16 => pixels.flat_map(|p| wide(luma(p))).collect(),
+            Format::LumaA8 => pixels.flat_map(|p| [luma(p), u8::MAX]).collect(),
+            Format::LumaA16 => pixels
+                .flat_map(|p| {
+                    let [l0, l1] = wide(luma(p));
+                    [l0, l1, u8::MAX, u8::MAX]
+                })
+                .collect(),
+            Format::Rgb8 => self.canvas.clone(),
+            Format::Rgb16 => self.canvas.iter().flat_map(|v| wide(*v)).collect(),
+            Format::Rgba8 => pixels.flat_map(|p| [p[0], p[1], p[2], u8::MAX]).collect(),
+            Format::Rgba16 => pixels
+                .flat_map(|p| {
+                    let (r, g, b) = (wide(p[0]), wide(p[1]), wide(p[2]));
+                    [r[0], r[1], g[0], g[1], b[0], b[1], u8::MAX, u8::MAX]
+                })
+                .collect(),
+            Format::MJpeg => {
+                let mut buf = Vec::new();
+                Encoder::new(&mut buf, JPEG_QUALITY)
+                    .encode(
+                        &self.canvas,
+                        self.width as u16,
+                        self.height as u16,
+                        ColorType::Rgb,
+                    )
+                    .map_err(|_| Error::InvalidFrame)?;
+                buf
+            }
+        })
+    }
+}
+
+/// Starts a background worker, which feeds the stream with test-pattern frames
+pub(crate) fn spawn(
+    stream: Stream,
+    format: Format,
+    width: u16,
+    height: u16,
+    interval: Duration,
+    pattern: TestPattern,
+) {
+    let stream_id = stream.id;
+    let mut renderer = Renderer::new(pattern, format, width, height);
+    let mut frame_number = 0;
+    source::spawn(stream, interval, move || {
+        match renderer.render(frame_number) {
+            Ok(data) => {
+                frame_number += 1;
+                Next::Frame(data.into())
+            }
+            Err(error) => {
+                warn!(stream_id, %error, "test pattern failed");
+                Next::Stop
+            }
+        }
+    });
+}
+
+/// Formats the time as `YYYY-MM-DD HH:MM:SS.mmm` (UTC)
+fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Converts days since the UNIX epoch into a (year, month, day) date
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// 5x7 glyphs of the overlay characters (unknown ones are drawn as spaces)
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{civil_from_days, utc_timestamp};
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(59), (1970, 3, 1));
+        // leap days
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+        // 2100 is not a leap year
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01 00:00:00.000");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(utc_timestamp(time), "2024-02-29 12:34:56.789");
+        let time = UNIX_EPOCH + Duration::from_secs(4_102_444_799);
+        assert_eq!(utc_timestamp(time), "2099-12-31 23:59:59.000");
+    }
+}
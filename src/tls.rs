@@ -0,0 +1,84 @@
+//! TLS configuration helpers. The frame-level protocol is the same for plain TCP and TLS
+//! connections.
+use std::sync::Arc;
+
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore,
+};
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+use crate::Error;
+
+fn certs(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| rustls::Error::General(e.to_string()))?;
+    if certs.is_empty() {
+        return Err(rustls::Error::General("no certificates found".to_owned()).into());
+    }
+    Ok(certs)
+}
+
+fn private_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>, Error> {
+    Ok(PrivateKeyDer::from_pem_slice(pem).map_err(|e| rustls::Error::General(e.to_string()))?)
+}
+
+fn root_store(pem: &[u8]) -> Result<RootCertStore, Error> {
+    let mut store = RootCertStore::empty();
+    for cert in certs(pem)? {
+        store
+            .add(cert)
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+    }
+    Ok(store)
+}
+
+/// Create a server configuration from a PEM-encoded certificate chain and a private key. If
+/// client CA certificates are provided, clients are required to authenticate with certificates,
+/// signed by one of them.
+pub fn server_config(
+    cert_chain_pem: &[u8],
+    key_pem: &[u8],
+    client_ca_pem: Option<&[u8]>,
+) -> Result<Arc<ServerConfig>, Error> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = if let Some(client_ca_pem) = client_ca_pem {
+        let verifier = WebPkiClientVerifier::builder_with_provider(
+            root_store(client_ca_pem)?.into(),
+            provider,
+        )
+        .build()
+        .map_err(|e| rustls::Error::General(e.to_string()))?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    Ok(Arc::new(builder.with_single_cert(
+        certs(cert_chain_pem)?,
+        private_key(key_pem)?,
+    )?))
+}
+
+/// Create a client configuration, which trusts the given PEM-encoded root certificates
+/// (self-signed end-entity server certificates can be used as roots as well). If a client certificate
+/// chain and a private key are provided, they are used to authenticate the client.
+pub fn client_config(
+    root_ca_pem: &[u8],
+    client_cert: Option<(&[u8], &[u8])>,
+) -> Result<Arc<ClientConfig>, Error> {
+    let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(root_store(root_ca_pem)?);
+    let config = if let Some((cert_chain_pem, key_pem)) = client_cert {
+        builder.with_client_auth_cert(certs(cert_chain_pem)?, private_key(key_pem)?)?
+    } else {
+        builder.with_no_client_auth()
+    };
+    Ok(Arc::new(config))
+}